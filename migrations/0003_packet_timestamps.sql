@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS packet_timestamps (
+   key TEXT PRIMARY KEY,
+   saved_at TIMESTAMPTZ NOT NULL,
+   expires_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS packet_timestamps_expires_at ON packet_timestamps (expires_at);
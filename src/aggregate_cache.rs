@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use axum::http::{HeaderName, HeaderValue};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use tracing::info;
+
+pub const CACHE_STATUS_HEADER: HeaderName = HeaderName::from_static("x-cache");
+/// Values computed while the cache is full are returned but not stored until the next invalidation.
+const MAX_ENTRIES: usize = 256;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CacheStatus {
+    Hit,
+    Miss,
+}
+
+impl CacheStatus {
+    pub fn header(&self) -> [(HeaderName, HeaderValue); 1] {
+        let value = match self {
+            CacheStatus::Hit => "HIT",
+            CacheStatus::Miss => "MISS",
+        };
+        [(CACHE_STATUS_HEADER, HeaderValue::from_static(value))]
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct CacheMetrics {
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_rate: f64,
+    pub invalidations: u64,
+}
+
+/// Caches aggregates computed from the orders and regions tables until they are changed through
+/// the API. Every invalidation starts a new generation, so a value computed while the data changed
+/// is returned but not stored.
+#[derive(Default)]
+pub struct AggregateCache {
+    entries: Mutex<(u64, HashMap<String, Value>)>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl AggregateCache {
+    pub async fn get_or_compute<T, E, F>(&self, key: &str, compute: F) -> Result<(T, CacheStatus), E>
+    where
+        T: Serialize + DeserializeOwned,
+        F: Future<Output = Result<T, E>>,
+    {
+        let (generation, cached) = {
+            let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+            (entries.0, entries.1.get(key).cloned())
+        };
+        if let Some(value) = cached.and_then(|value| serde_json::from_value(value).ok()) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok((value, CacheStatus::Hit));
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let value = compute.await?;
+        if let Ok(json) = serde_json::to_value(&value) {
+            let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+            if entries.0 == generation && entries.1.len() < MAX_ENTRIES {
+                entries.1.insert(key.to_string(), json);
+            }
+        }
+        Ok((value, CacheStatus::Miss))
+    }
+
+    pub fn invalidate(&self) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.0 += 1;
+        entries.1.clear();
+        info!("Invalidated aggregate cache.");
+    }
+
+    pub fn metrics(&self) -> CacheMetrics {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        CacheMetrics {
+            entries: entries.1.len(),
+            hits,
+            misses,
+            hit_rate: if hits + misses == 0 { 0.0 } else { hits as f64 / (hits + misses) as f64 },
+            invalidations: entries.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AggregateCache, CacheStatus, MAX_ENTRIES};
+
+    #[tokio::test]
+    async fn test_cache() {
+        let cache = AggregateCache::default();
+        assert_eq!(cache.get_or_compute("total", async { Ok::<_, ()>(5) }).await, Ok((5, CacheStatus::Miss)));
+        assert_eq!(cache.get_or_compute("total", async { Ok::<_, ()>(6) }).await, Ok((5, CacheStatus::Hit)));
+        assert_eq!(cache.get_or_compute("other", async { Err::<i32, _>("failed") }).await, Err("failed"));
+        cache.invalidate();
+        assert_eq!(cache.get_or_compute("total", async { Ok::<_, ()>(7) }).await, Ok((7, CacheStatus::Miss)));
+        let metrics = cache.metrics();
+        assert_eq!((metrics.hits, metrics.misses, metrics.entries, metrics.invalidations), (1, 3, 1, 1));
+        assert_eq!(metrics.hit_rate, 0.25);
+    }
+
+    #[tokio::test]
+    async fn test_invalidation_during_computation() {
+        let cache = AggregateCache::default();
+        let result = cache.get_or_compute("total", async {
+            cache.invalidate();
+            Ok::<_, ()>(5)
+        }).await;
+        assert_eq!(result, Ok((5, CacheStatus::Miss)));
+        assert_eq!(cache.metrics().entries, 0);
+    }
+
+    #[tokio::test]
+    async fn test_max_entries() {
+        let cache = AggregateCache::default();
+        for index in 0..=MAX_ENTRIES {
+            cache.get_or_compute(&index.to_string(), async { Ok::<_, ()>(index) }).await.unwrap();
+        }
+        assert_eq!(cache.metrics().entries, MAX_ENTRIES);
+        let key = MAX_ENTRIES.to_string();
+        assert_eq!(cache.get_or_compute(&key, async { Ok::<_, ()>(0) }).await, Ok((0, CacheStatus::Miss)));
+        assert_eq!(cache.get_or_compute("0", async { Ok::<_, ()>(1) }).await, Ok((0, CacheStatus::Hit)));
+    }
+}
@@ -0,0 +1,207 @@
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use axum::http::StatusCode;
+use image::ImageFormat;
+use image::io::Reader;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tracing::info;
+use uuid::Uuid;
+
+const ALLOWED_FORMATS: &[ImageFormat] = &[ImageFormat::Png, ImageFormat::Jpeg, ImageFormat::Gif, ImageFormat::WebP];
+const MAX_DEPTH: usize = 4;
+
+#[derive(Debug, PartialEq)]
+pub enum AssetError {
+    InvalidPath(String),
+    TooLarge(usize),
+    UnsupportedType(String),
+    Exists(String),
+    NotFound(String),
+    Io(String),
+}
+
+impl From<AssetError> for (StatusCode, String) {
+    fn from(error: AssetError) -> (StatusCode, String) {
+        match error {
+            AssetError::InvalidPath(path) => (StatusCode::BAD_REQUEST, format!("invalid asset path '{}'", path)),
+            AssetError::TooLarge(max) => (StatusCode::PAYLOAD_TOO_LARGE, format!("assets may have at most {} bytes", max)),
+            AssetError::UnsupportedType(message) => (StatusCode::UNSUPPORTED_MEDIA_TYPE, message),
+            AssetError::Exists(path) => (StatusCode::CONFLICT, format!("asset {} already exists", path)),
+            AssetError::NotFound(path) => (StatusCode::NOT_FOUND, format!("asset {} not found", path)),
+            AssetError::Io(message) => (StatusCode::INTERNAL_SERVER_ERROR, message),
+        }
+    }
+}
+
+impl From<std::io::Error> for AssetError {
+    fn from(error: std::io::Error) -> AssetError {
+        AssetError::Io(error.to_string())
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct AssetInfo {
+    pub path: String,
+    pub size: u64,
+    pub content_type: &'static str,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub sha256: String,
+}
+
+/// Manages the images in the directory that is served as `/11/assets`.
+#[derive(Clone, Debug)]
+pub struct AssetStore {
+    dir: PathBuf,
+    max_bytes: usize,
+}
+
+impl AssetStore {
+    pub fn new(dir: PathBuf, max_bytes: usize) -> AssetStore {
+        AssetStore { dir, max_bytes }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    /// Only plain names made of letters, digits, `.`, `-` and `_` are accepted for each segment,
+    /// which rules out `..`, absolute paths, hidden files and anything a shell or browser would treat
+    /// specially.
+    pub fn resolve(&self, path: &str) -> Result<PathBuf, AssetError> {
+        let segments: Vec<&str> = path.split('/').collect();
+        let valid_segment = |segment: &&str| {
+            !segment.is_empty() && !segment.starts_with('.') && segment.len() <= 100
+                && segment.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+        };
+        if segments.len() > MAX_DEPTH || !segments.iter().all(valid_segment) {
+            return Err(AssetError::InvalidPath(path.to_string()));
+        }
+        Ok(self.dir.join(segments.join("/")))
+    }
+
+    /// Stores an image, sniffing its type from the content and checking that the extension agrees.
+    pub async fn put(&self, path: &str, data: &[u8], replace: bool) -> Result<AssetInfo, AssetError> {
+        let target = self.resolve(path)?;
+        if data.len() > self.max_bytes {
+            return Err(AssetError::TooLarge(self.max_bytes));
+        }
+        let format = image::guess_format(data).ok()
+            .filter(|format| ALLOWED_FORMATS.contains(format))
+            .ok_or(AssetError::UnsupportedType("assets must be PNG, JPEG, GIF or WebP images".to_string()))?;
+        if ImageFormat::from_path(&target).ok() != Some(format) {
+            return Err(AssetError::UnsupportedType(format!("the extension of {} does not match its {} content", path, format.to_mime_type())));
+        }
+        if !replace && tokio::fs::try_exists(&target).await? {
+            return Err(AssetError::Exists(path.to_string()));
+        }
+        let parent = target.parent().unwrap_or(&self.dir);
+        tokio::fs::create_dir_all(parent).await?;
+        let temporary = parent.join(format!(".{}.tmp", Uuid::new_v4()));
+        tokio::fs::write(&temporary, data).await?;
+        tokio::fs::rename(&temporary, &target).await?;
+        info!("Stored asset {} ({} bytes)", path, data.len());
+        Ok(describe(path, data))
+    }
+
+    pub async fn delete(&self, path: &str) -> Result<(), AssetError> {
+        let target = self.resolve(path)?;
+        match tokio::fs::remove_file(&target).await {
+            Ok(()) => {
+                info!("Deleted asset {}", path);
+                Ok(())
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(AssetError::NotFound(path.to_string())),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn list(&self) -> Result<Vec<AssetInfo>, AssetError> {
+        let dir = self.dir.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut assets = Vec::new();
+            collect(&dir, "", &mut assets)?;
+            assets.sort_by(|a: &AssetInfo, b| a.path.cmp(&b.path));
+            Ok(assets)
+        }).await.map_err(|e| AssetError::Io(e.to_string()))?
+    }
+}
+
+fn collect(dir: &Path, prefix: &str, assets: &mut Vec<AssetInfo>) -> Result<(), AssetError> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') {
+            continue;
+        }
+        let path = format!("{}{}", prefix, name);
+        if entry.file_type()?.is_dir() {
+            collect(&entry.path(), &format!("{}/", path), assets)?;
+        } else {
+            assets.push(describe(&path, &std::fs::read(entry.path())?));
+        }
+    }
+    Ok(())
+}
+
+fn describe(path: &str, data: &[u8]) -> AssetInfo {
+    let format = image::guess_format(data).ok();
+    let dimensions = format.and_then(|format| Reader::with_format(Cursor::new(data), format).into_dimensions().ok());
+    AssetInfo {
+        path: path.to_string(),
+        size: data.len() as u64,
+        content_type: format.map(|format| format.to_mime_type()).unwrap_or("application/octet-stream"),
+        width: dimensions.map(|(width, _)| width),
+        height: dimensions.map(|(_, height)| height),
+        sha256: format!("{:x}", Sha256::digest(data)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use super::{AssetError, AssetStore};
+
+    fn store() -> AssetStore {
+        AssetStore::new(std::env::temp_dir().join(format!("cch23-assets-{}", uuid::Uuid::new_v4())), 1_000_000)
+    }
+
+    #[test]
+    fn test_resolve() {
+        let store = AssetStore::new(PathBuf::from("assets"), 1000);
+        assert_eq!(store.resolve("icons/star.png"), Ok(PathBuf::from("assets/icons/star.png")));
+        for path in ["../secret.png", "/etc/passwd", "icons/../../x.png", ".hidden.png", "a b.png", "", "a//b.png", "a/b/c/d/e.png"] {
+            assert_eq!(store.resolve(path), Err(AssetError::InvalidPath(path.to_string())));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_put_list_delete() {
+        let store = store();
+        let png = std::fs::read("assets/decoration.png").unwrap();
+        let info = store.put("icons/decoration.png", &png, false).await.unwrap();
+        assert_eq!((info.content_type, info.width.is_some()), ("image/png", true));
+        assert_eq!(store.put("icons/decoration.png", &png, false).await.unwrap_err(), AssetError::Exists("icons/decoration.png".to_string()));
+        assert!(store.put("icons/decoration.png", &png, true).await.is_ok());
+        assert!(matches!(store.put("decoration.jpg", &png, false).await, Err(AssetError::UnsupportedType(_))));
+        assert!(matches!(store.put("notes.png", b"plain text", false).await, Err(AssetError::UnsupportedType(_))));
+
+        let assets = store.list().await.unwrap();
+        assert_eq!(assets, vec![info]);
+        store.delete("icons/decoration.png").await.unwrap();
+        assert_eq!(store.delete("icons/decoration.png").await, Err(AssetError::NotFound("icons/decoration.png".to_string())));
+        assert!(store.list().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_size_limit() {
+        let store = AssetStore::new(std::env::temp_dir(), 10);
+        let png = std::fs::read("assets/decoration.png").unwrap();
+        assert_eq!(store.put("decoration.png", &png, true).await.unwrap_err(), AssetError::TooLarge(10));
+    }
+}
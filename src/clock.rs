@@ -0,0 +1,129 @@
+use std::env;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use tracing::info;
+
+const ENVIRONMENT_VARIABLE: &str = "APP_ENV";
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct ClockState {
+    pub now: DateTime<Utc>,
+    pub frozen: bool,
+}
+
+/// A clock that follows the system clock with an offset, or stands still while frozen.
+pub struct TestClock {
+    state: Mutex<(Option<DateTime<Utc>>, Duration)>,
+}
+
+impl Default for TestClock {
+    fn default() -> TestClock {
+        TestClock { state: Mutex::new((None, Duration::zero())) }
+    }
+}
+
+impl TestClock {
+    #[cfg(test)]
+    pub fn frozen_at(at: DateTime<Utc>) -> TestClock {
+        TestClock { state: Mutex::new((Some(at), Duration::zero())) }
+    }
+
+    /// Freezes the clock at the given time, or at the current time of this clock.
+    pub fn freeze(&self, at: Option<DateTime<Utc>>) -> ClockState {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let now = at.unwrap_or(state.0.unwrap_or(Utc::now() + state.1));
+        *state = (Some(now), Duration::zero());
+        ClockState { now, frozen: true }
+    }
+
+    /// Lets the clock run again from the time it was frozen at.
+    pub fn resume(&self) -> ClockState {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(frozen) = state.0 {
+            *state = (None, frozen - Utc::now());
+        }
+        ClockState { now: Utc::now() + state.1, frozen: false }
+    }
+
+    /// Moves the clock by `duration`, or returns `None` and leaves it alone if the result would be
+    /// out of range.
+    pub fn advance(&self, duration: Duration) -> Option<ClockState> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match state.0 {
+            Some(frozen) => state.0 = Some(frozen.checked_add_signed(duration)?),
+            None => {
+                let offset = state.1.checked_add(&duration)?;
+                Utc::now().checked_add_signed(offset)?;
+                state.1 = offset;
+            }
+        }
+        drop(state);
+        Some(self.state())
+    }
+
+    pub fn state(&self) -> ClockState {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        ClockState { now: state.0.unwrap_or(Utc::now() + state.1), frozen: state.0.is_some() }
+    }
+}
+
+impl Clock for TestClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.state().now
+    }
+}
+
+/// Uses the system clock in production. Elsewhere, which is when `APP_ENV` is `development` or
+/// `test`, a test clock is returned as well, so that time can be controlled at runtime.
+pub fn from_env() -> (Arc<dyn Clock>, Option<Arc<TestClock>>) {
+    let environment = env::var(ENVIRONMENT_VARIABLE).unwrap_or_default();
+    if cfg!(test) || environment == "development" || environment == "test" {
+        info!("Using a controllable clock.");
+        let clock = Arc::new(TestClock::default());
+        return (clock.clone(), Some(clock));
+    }
+    (Arc::new(SystemClock), None)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+    use super::{Clock, TestClock};
+
+    #[test]
+    fn test_test_clock() {
+        let start = Utc.with_ymd_and_hms(2023, 12, 12, 12, 0, 0).unwrap();
+        let clock = TestClock::frozen_at(start);
+        assert_eq!(clock.advance(Duration::seconds(2)).unwrap().now, start + Duration::seconds(2));
+        assert_eq!(clock.now(), start + Duration::seconds(2));
+        let resumed = clock.resume();
+        assert!(!resumed.frozen);
+        assert!(clock.now() - start >= Duration::seconds(2) && clock.now() - start < Duration::seconds(60));
+        assert!(clock.freeze(None).frozen);
+        assert!(clock.now() - start >= Duration::seconds(2));
+    }
+
+    #[test]
+    fn test_advance_out_of_range() {
+        let start = Utc.with_ymd_and_hms(2023, 12, 12, 12, 0, 0).unwrap();
+        let clock = TestClock::frozen_at(start);
+        assert_eq!(clock.advance(Duration::max_value()), None);
+        assert_eq!(clock.now(), start);
+        clock.resume();
+        assert_eq!(clock.advance(Duration::min_value()), None);
+        assert!(clock.now() - start < Duration::seconds(60));
+    }
+}
@@ -0,0 +1,372 @@
+use std::cmp::Reverse;
+use std::io::Cursor;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use image::{DynamicImage, GrayImage, ImageOutputFormat, Luma, Rgba};
+use serde::{Deserialize, Serialize};
+
+const NAMED_COLOURS: &[(&str, [u8; 3])] = &[
+    ("black", [0, 0, 0]),
+    ("white", [255, 255, 255]),
+    ("red", [255, 0, 0]),
+    ("green", [0, 128, 0]),
+    ("lime", [0, 255, 0]),
+    ("blue", [0, 0, 255]),
+    ("yellow", [255, 255, 0]),
+    ("cyan", [0, 255, 255]),
+    ("magenta", [255, 0, 255]),
+    ("orange", [255, 165, 0]),
+    ("purple", [128, 0, 128]),
+    ("pink", [255, 192, 203]),
+    ("brown", [165, 42, 42]),
+    ("gray", [128, 128, 128]),
+    ("grey", [128, 128, 128]),
+    ("gold", [255, 215, 0]),
+    ("silver", [192, 192, 192]),
+];
+
+const KMEANS_SAMPLES: usize = 10_000;
+const MAX_DOMINANT: usize = 16;
+const KMEANS_ITERATIONS: usize = 20;
+
+/// Parses a named colour or a `#rrggbb` hex code.
+pub fn named_colour(name: &str) -> Result<[u8; 3], String> {
+    let name = name.trim().to_lowercase();
+    if let Some(hex) = name.strip_prefix('#') {
+        let channel = |index: usize| hex.get(index..index + 2).and_then(|value| u8::from_str_radix(value, 16).ok());
+        return match (hex.len(), channel(0), channel(2), channel(4)) {
+            (6, Some(red), Some(green), Some(blue)) => Ok([red, green, blue]),
+            _ => Err(format!("invalid colour '{}'", name)),
+        };
+    }
+    NAMED_COLOURS.iter()
+        .find(|(known, _)| *known == name)
+        .map(|(_, rgb)| *rgb)
+        .ok_or(format!("unknown colour '{}'", name))
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Range {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Range {
+    fn contains(&self, value: f32) -> bool {
+        self.min <= value && value <= self.max
+    }
+
+    /// Like `contains`, but a range with `min > max` wraps around, e.g. hues from 330 to 30 degrees.
+    fn contains_circular(&self, value: f32) -> bool {
+        if self.min <= self.max { self.contains(value) } else { value >= self.min || value <= self.max }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ColourPredicate {
+    /// Red is brighter than green and blue together.
+    #[default]
+    RedDominant,
+    /// Channel thresholds from 0 to 255, a missing channel matches everything.
+    Rgb { red: Option<Range>, green: Option<Range>, blue: Option<Range> },
+    /// Hue in degrees from 0 to 360, saturation and value from 0 to 1.
+    Hsv { hue: Option<Range>, saturation: Option<Range>, value: Option<Range> },
+    /// Euclidean RGB distance to a named colour or `#rrggbb`.
+    Named {
+        name: String,
+        #[serde(default = "default_tolerance")]
+        tolerance: f32,
+    },
+}
+
+fn default_tolerance() -> f32 {
+    60.0
+}
+
+impl ColourPredicate {
+    /// Resolves colour names once, so that matching a pixel can not fail.
+    pub fn compile(&self) -> Result<Matcher, String> {
+        Ok(match self {
+            ColourPredicate::RedDominant => Matcher::RedDominant,
+            ColourPredicate::Rgb { red, green, blue } => Matcher::Rgb([*red, *green, *blue]),
+            ColourPredicate::Hsv { hue, saturation, value } => Matcher::Hsv(*hue, *saturation, *value),
+            ColourPredicate::Named { name, tolerance } if *tolerance >= 0.0 => Matcher::Near(named_colour(name)?, *tolerance),
+            ColourPredicate::Named { .. } => return Err("tolerance must not be negative".to_string()),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Matcher {
+    RedDominant,
+    Rgb([Option<Range>; 3]),
+    Hsv(Option<Range>, Option<Range>, Option<Range>),
+    Near([u8; 3], f32),
+}
+
+impl Matcher {
+    pub fn matches(&self, pixel: &Rgba<u8>) -> bool {
+        let [red, green, blue, _] = pixel.0;
+        match self {
+            Matcher::RedDominant => red as i32 > green as i32 + blue as i32,
+            Matcher::Rgb(ranges) => ranges.iter().zip([red, green, blue])
+                .all(|(range, channel)| range.is_none_or(|range| range.contains(channel as f32))),
+            Matcher::Hsv(hue, saturation, value) => {
+                let (h, s, v) = hsv(red, green, blue);
+                hue.is_none_or(|range| range.contains_circular(h))
+                    && saturation.is_none_or(|range| range.contains(s))
+                    && value.is_none_or(|range| range.contains(v))
+            }
+            Matcher::Near(colour, tolerance) => distance([red as f32, green as f32, blue as f32], colour.map(|c| c as f32)) <= *tolerance,
+        }
+    }
+}
+
+fn hsv(red: u8, green: u8, blue: u8) -> (f32, f32, f32) {
+    let (r, g, b) = (red as f32 / 255.0, green as f32 / 255.0, blue as f32 / 255.0);
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    let saturation = if max == 0.0 { 0.0 } else { delta / max };
+    (hue, saturation, max)
+}
+
+fn distance(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| (a - b).powi(2)).sum::<f32>().sqrt()
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AlphaPolicy {
+    /// Ignores the alpha channel, a transparent red pixel is still red.
+    #[default]
+    Include,
+    /// Leaves fully transparent pixels out of the counts, histograms and dominant colours.
+    Exclude,
+}
+
+impl AlphaPolicy {
+    pub fn admits(&self, pixel: &Rgba<u8>) -> bool {
+        *self == AlphaPolicy::Include || pixel[3] > 0
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct AnalysisOptions {
+    pub predicate: ColourPredicate,
+    pub alpha: Option<AlphaPolicy>,
+    pub histogram: bool,
+    /// Number of histogram bins per channel, from 1 to 256.
+    pub bins: usize,
+    /// Number of dominant colours to extract, up to 16, 0 to skip the clustering.
+    pub dominant: usize,
+    /// Whether to include a PNG of the matching pixels, white on black.
+    pub mask: bool,
+}
+
+impl Default for AnalysisOptions {
+    fn default() -> AnalysisOptions {
+        AnalysisOptions { predicate: ColourPredicate::default(), alpha: None, histogram: true, bins: 256, dominant: 5, mask: false }
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Histograms {
+    pub red: Vec<u64>,
+    pub green: Vec<u64>,
+    pub blue: Vec<u64>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct DominantColour {
+    pub colour: String,
+    pub share: f64,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Analysis {
+    pub width: u32,
+    pub height: u32,
+    pub matching: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub histograms: Option<Histograms>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub dominant: Vec<DominantColour>,
+    /// Base64-encoded PNG.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mask: Option<String>,
+}
+
+pub fn count_matching(image: &DynamicImage, matcher: &Matcher, alpha: AlphaPolicy) -> u64 {
+    image.to_rgba8().pixels().filter(|pixel| alpha.admits(pixel) && matcher.matches(pixel)).count() as u64
+}
+
+pub fn analyse(image: &DynamicImage, options: &AnalysisOptions) -> Result<Analysis, String> {
+    if !(1..=256).contains(&options.bins) {
+        return Err("bins must be between 1 and 256".to_string());
+    }
+    if options.dominant > MAX_DOMINANT {
+        return Err(format!("dominant must be at most {}", MAX_DOMINANT));
+    }
+    let matcher = options.predicate.compile()?;
+    let alpha = options.alpha.unwrap_or_default();
+    let rgba = image.to_rgba8();
+    let mut histograms = Histograms { red: vec![0; options.bins], green: vec![0; options.bins], blue: vec![0; options.bins] };
+    let mut mask = options.mask.then(|| GrayImage::new(rgba.width(), rgba.height()));
+    let mut matching = 0;
+    let mut admitted: usize = 0;
+    for (x, y, pixel) in rgba.enumerate_pixels().filter(|(_, _, pixel)| alpha.admits(pixel)) {
+        admitted += 1;
+        if matcher.matches(pixel) {
+            matching += 1;
+            if let Some(mask) = mask.as_mut() {
+                mask.put_pixel(x, y, Luma([255]));
+            }
+        }
+        let bin = |channel: u8| channel as usize * options.bins / 256;
+        histograms.red[bin(pixel[0])] += 1;
+        histograms.green[bin(pixel[1])] += 1;
+        histograms.blue[bin(pixel[2])] += 1;
+    }
+    let sample: Vec<[f32; 3]> = match options.dominant {
+        0 => Vec::new(),
+        _ => rgba.pixels().filter(|pixel| alpha.admits(pixel))
+            .step_by(admitted.div_ceil(KMEANS_SAMPLES).max(1))
+            .map(|pixel| [pixel[0] as f32, pixel[1] as f32, pixel[2] as f32])
+            .collect(),
+    };
+    let mask = match mask {
+        Some(mask) => {
+            let mut png = Cursor::new(Vec::new());
+            DynamicImage::ImageLuma8(mask).write_to(&mut png, ImageOutputFormat::Png).map_err(|e| e.to_string())?;
+            Some(STANDARD.encode(png.into_inner()))
+        }
+        None => None,
+    };
+    Ok(Analysis {
+        width: rgba.width(),
+        height: rgba.height(),
+        matching,
+        histograms: options.histogram.then_some(histograms),
+        dominant: dominant_colours(sample, options.dominant),
+        mask,
+    })
+}
+
+/// Clusters a sample of the pixels with k-means. The centroids start at evenly spaced quantiles of
+/// the brightness, which keeps the result deterministic.
+pub fn dominant_colours(mut sample: Vec<[f32; 3]>, k: usize) -> Vec<DominantColour> {
+    if k == 0 || sample.is_empty() {
+        return Vec::new();
+    }
+    sample.sort_by(|a, b| a.iter().sum::<f32>().total_cmp(&b.iter().sum::<f32>()));
+    let k = k.min(sample.len());
+    let mut centroids: Vec<[f32; 3]> = (0..k).map(|i| sample[(2 * i + 1) * sample.len() / (2 * k)]).collect();
+    let mut assignments = vec![0; sample.len()];
+    for _ in 0..KMEANS_ITERATIONS {
+        let mut changed = false;
+        for (pixel, assignment) in sample.iter().zip(assignments.iter_mut()) {
+            let nearest = (0..k).min_by(|a, b| distance(*pixel, centroids[*a]).total_cmp(&distance(*pixel, centroids[*b]))).unwrap_or(0);
+            changed |= nearest != *assignment;
+            *assignment = nearest;
+        }
+        let mut sums = vec![([0f32; 3], 0usize); k];
+        for (pixel, assignment) in sample.iter().zip(&assignments) {
+            let (sum, count) = &mut sums[*assignment];
+            sum.iter_mut().zip(pixel).for_each(|(sum, channel)| *sum += channel);
+            *count += 1;
+        }
+        for (centroid, (sum, count)) in centroids.iter_mut().zip(sums) {
+            if count > 0 {
+                *centroid = sum.map(|channel| channel / count as f32);
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    let mut counts = vec![0usize; k];
+    assignments.iter().for_each(|assignment| counts[*assignment] += 1);
+    let mut dominant: Vec<(usize, [f32; 3])> = counts.into_iter().zip(centroids).filter(|(count, _)| *count > 0).collect();
+    dominant.sort_by_key(|(count, _)| Reverse(*count));
+    dominant.into_iter()
+        .map(|(count, [red, green, blue])| DominantColour {
+            colour: format!("#{:02x}{:02x}{:02x}", red.round() as u8, green.round() as u8, blue.round() as u8),
+            share: count as f64 / sample.len() as f64,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, Rgba, RgbaImage};
+    use super::{analyse, named_colour, AlphaPolicy, AnalysisOptions, ColourPredicate, Range};
+
+    fn image() -> DynamicImage {
+        let mut image = RgbaImage::from_pixel(4, 2, Rgba([250, 10, 10, 255]));
+        for x in 0..4 {
+            image.put_pixel(x, 1, Rgba([10, 10, 240, 255]));
+        }
+        image.put_pixel(0, 0, Rgba([200, 150, 100, 255]));
+        DynamicImage::ImageRgba8(image)
+    }
+
+    fn matching(predicate: ColourPredicate) -> u64 {
+        analyse(&image(), &AnalysisOptions { predicate, ..Default::default() }).unwrap().matching
+    }
+
+    #[test]
+    fn test_predicates() {
+        assert_eq!(matching(ColourPredicate::RedDominant), 3);
+        assert_eq!(matching(ColourPredicate::Rgb { red: None, green: None, blue: Some(Range { min: 200.0, max: 255.0 }) }), 4);
+        assert_eq!(matching(ColourPredicate::Hsv { hue: Some(Range { min: 330.0, max: 30.0 }), saturation: Some(Range { min: 0.8, max: 1.0 }), value: None }), 3);
+        assert_eq!(matching(ColourPredicate::Named { name: "Blue".to_string(), tolerance: 25.0 }), 4);
+        assert_eq!(matching(ColourPredicate::Named { name: "#c89664".to_string(), tolerance: 0.0 }), 1);
+        assert!(ColourPredicate::Named { name: "octarine".to_string(), tolerance: 1.0 }.compile().is_err());
+        assert_eq!(named_colour("#FFA500"), named_colour("orange"));
+    }
+
+    #[test]
+    fn test_histograms_and_mask() {
+        let analysis = analyse(&image(), &AnalysisOptions { bins: 2, mask: true, ..Default::default() }).unwrap();
+        let histograms = analysis.histograms.unwrap();
+        assert_eq!(histograms.red, vec![4, 4]);
+        assert_eq!(histograms.blue, vec![4, 4]);
+        let mask = image::load_from_memory(&base64::Engine::decode(&base64::engine::general_purpose::STANDARD, analysis.mask.unwrap()).unwrap()).unwrap().to_luma8();
+        assert_eq!(mask.get_pixel(1, 0).0, [255]);
+        assert_eq!(mask.get_pixel(1, 1).0, [0]);
+        assert!(analyse(&image(), &AnalysisOptions { bins: 0, ..Default::default() }).is_err());
+    }
+
+    #[test]
+    fn test_dominant_colours() {
+        let dominant = analyse(&image(), &AnalysisOptions { dominant: 2, ..Default::default() }).unwrap().dominant;
+        assert_eq!(dominant.len(), 2);
+        assert_eq!(dominant[0].share, 0.5);
+        assert_eq!(dominant.iter().map(|colour| colour.colour.as_str()).collect::<Vec<_>>(), vec!["#0a0af0", "#ee2d21"]);
+        assert!(analyse(&image(), &AnalysisOptions { dominant: 0, ..Default::default() }).unwrap().dominant.is_empty());
+        assert!(analyse(&image(), &AnalysisOptions { dominant: 16, ..Default::default() }).is_ok());
+        assert_eq!(analyse(&image(), &AnalysisOptions { dominant: 10_000, ..Default::default() }), Err("dominant must be at most 16".to_string()));
+    }
+
+    #[test]
+    fn test_alpha_policy() {
+        let mut image = image().to_rgba8();
+        image.put_pixel(1, 0, Rgba([255, 0, 0, 0]));
+        let image = DynamicImage::ImageRgba8(image);
+        let included = analyse(&image, &AnalysisOptions::default()).unwrap();
+        let excluded = analyse(&image, &AnalysisOptions { alpha: Some(AlphaPolicy::Exclude), ..Default::default() }).unwrap();
+        assert_eq!((included.matching, excluded.matching), (3, 2));
+        assert_eq!(excluded.histograms.unwrap().red.iter().sum::<u64>(), 7);
+    }
+}
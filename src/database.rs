@@ -0,0 +1,40 @@
+use axum::http::StatusCode;
+use sqlx::PgPool;
+use tracing::error;
+
+/// SQLSTATE of a query on a table that does not exist, e.g. before `/reset` was called.
+const UNDEFINED_TABLE: &str = "42P01";
+/// SQLSTATEs of unique, not null, foreign key and check constraint violations, which are caused by
+/// the submitted data.
+const CONSTRAINT_VIOLATIONS: [&str; 4] = ["23505", "23502", "23503", "23514"];
+
+pub fn pool(pool: &Option<PgPool>) -> Result<&PgPool, StatusCode> {
+    pool.as_ref().ok_or(StatusCode::SERVICE_UNAVAILABLE)
+}
+
+pub fn is_undefined_table(e: &sqlx::Error) -> bool {
+    matches!(e, sqlx::Error::Database(e) if e.code().as_deref() == Some(UNDEFINED_TABLE))
+}
+
+/// Maps a failed read to 404 when the tables have not been created yet and logs anything else as
+/// a 500.
+pub fn read_error(context: &str, e: sqlx::Error) -> StatusCode {
+    if is_undefined_table(&e) {
+        return StatusCode::NOT_FOUND;
+    }
+    error!("{} failed: {}", context, e);
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+/// Maps a failed write to 404 when the tables have not been created yet and to 400 when it
+/// violates a constraint, like a duplicate id, and logs anything else as a 500.
+pub fn write_error(context: &str, e: sqlx::Error) -> StatusCode {
+    if is_undefined_table(&e) {
+        return StatusCode::NOT_FOUND;
+    }
+    if matches!(&e, sqlx::Error::Database(e) if e.code().is_some_and(|code| CONSTRAINT_VIOLATIONS.contains(&code.as_ref()))) {
+        return StatusCode::BAD_REQUEST;
+    }
+    error!("{} failed: {}", context, e);
+    StatusCode::INTERNAL_SERVER_ERROR
+}
@@ -1,13 +1,34 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use axum::extract::State;
 use axum::http::StatusCode;
 use axum::Json;
 use axum::routing::{post};
+use handlebars::{no_escape, Handlebars};
 use serde::{Deserialize, Serialize};
-use tracing::info;
+use serde_json::{Map, Value};
+use tracing::{error, info};
+
+#[derive(Clone)]
+struct Day04State {
+    template_engine: Arc<Handlebars<'static>>,
+    categories: Arc<Vec<ContestCategory>>,
+}
 
 pub fn router() -> axum::Router {
+    info!("Initializing contest template engine.");
+    let mut hbs = Handlebars::new();
+    hbs.register_escape_fn(no_escape);
+    let shared_state = Day04State {
+        template_engine: Arc::new(hbs),
+        categories: Arc::new(default_categories()),
+    };
+
     axum::Router::new()
         .route("/strength", post(day04_post))
         .route("/contest", post(day04_post_contest))
+        .route("/contest/custom", post(day04_post_contest_custom))
+        .with_state(shared_state)
 }
 
 async fn day04_post(Json(reindeers): Json<Vec<Reindeer>>) -> Result<String, StatusCode> {
@@ -16,31 +37,92 @@ async fn day04_post(Json(reindeers): Json<Vec<Reindeer>>) -> Result<String, Stat
     Ok(format!("{}", strength))
 }
 
-async fn day04_post_contest(Json(reindeers): Json<Vec<ContestReindeer>>) -> Result<Json<ContestResult>, StatusCode> {
+async fn day04_post_contest(State(state): State<Day04State>, Json(reindeers): Json<Vec<ContestReindeer>>) -> Result<Json<ContestResult>, StatusCode> {
     info!("Got reindeers: {:?}", reindeers);
-    let fastest: &ContestReindeer = reindeers
-        .iter()
-        .max_by(|reindeer1, reindeer2| reindeer1.speed.partial_cmp(&reindeer2.speed).unwrap())
-        .ok_or(StatusCode::BAD_REQUEST)?;
-    let tallest: &ContestReindeer = reindeers
-        .iter()
-        .max_by_key(|reindeer| reindeer.height)
-        .ok_or(StatusCode::BAD_REQUEST)?;
-    let magician: &ContestReindeer = reindeers
-        .iter()
-        .max_by_key(|reindeer| reindeer.snow_magic_power)
-        .ok_or(StatusCode::BAD_REQUEST)?;
-    let consumer: &ContestReindeer = reindeers
-        .iter()
-        .max_by_key(|reindeer| reindeer.candies_eaten_yesterday)
-        .ok_or(StatusCode::BAD_REQUEST)?;
-
-    Ok(Json(ContestResult {
-        fastest: format!("Speeding past the finish line with a strength of {} is {}", fastest.strength, fastest.name),
-        tallest: format!("{} is standing tall with his {} cm wide antlers", tallest.name, tallest.antler_width),
-        magician: format!("{} could blast you away with a snow magic power of {}", magician.name, magician.snow_magic_power),
-        consumer: format!("{} ate lots of candies, but also some {}", consumer.name, consumer.favorite_food),
-    }))
+    let reindeers: Vec<Map<String, Value>> = reindeers.iter()
+        .map(|reindeer| match serde_json::to_value(reindeer) {
+            Ok(Value::Object(reindeer)) => Ok(reindeer),
+            _ => Err(StatusCode::INTERNAL_SERVER_ERROR),
+        })
+        .collect::<Result<_, _>>()?;
+    run_contest(&state.template_engine, &state.categories, &reindeers).map(Json)
+}
+
+#[derive(Deserialize, Debug)]
+struct CustomContest {
+    reindeers: Vec<Map<String, Value>>,
+    categories: Option<Vec<ContestCategory>>,
+}
+
+async fn day04_post_contest_custom(State(state): State<Day04State>, Json(contest): Json<CustomContest>) -> Result<Json<ContestResult>, StatusCode> {
+    info!("Got custom contest: {:?}", contest);
+    let categories = contest.categories.as_ref().unwrap_or(&state.categories);
+    run_contest(&state.template_engine, categories, &contest.reindeers).map(Json)
+}
+
+fn run_contest(hbs: &Handlebars, categories: &[ContestCategory], reindeers: &[Map<String, Value>]) -> Result<ContestResult, StatusCode> {
+    let mut result = BTreeMap::new();
+    for category in categories {
+        let winner = category.winner(reindeers)?;
+        let message = hbs.render_template(&category.template, winner)
+            .map_err(|e| { error!("Could not render template for category {}: {}", category.name, e); StatusCode::BAD_REQUEST })?;
+        result.insert(category.name.clone(), message);
+    }
+    Ok(ContestResult(result))
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Ranking {
+    Min,
+    Max,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct ContestCategory {
+    name: String,
+    field: String,
+    #[serde(default = "default_ranking")]
+    ranking: Ranking,
+    template: String,
+}
+
+fn default_ranking() -> Ranking {
+    Ranking::Max
+}
+
+impl ContestCategory {
+    fn new(name: &str, field: &str, template: &str) -> ContestCategory {
+        ContestCategory {
+            name: name.to_string(),
+            field: field.to_string(),
+            ranking: Ranking::Max,
+            template: template.to_string(),
+        }
+    }
+
+    fn winner<'a>(&self, reindeers: &'a [Map<String, Value>]) -> Result<&'a Map<String, Value>, StatusCode> {
+        let scores = reindeers.iter()
+            .map(|reindeer| reindeer.get(&self.field)
+                .and_then(Value::as_f64)
+                .map(|score| (score, reindeer))
+                .ok_or(StatusCode::BAD_REQUEST))
+            .collect::<Result<Vec<_>, _>>()?;
+        let winner = match self.ranking {
+            Ranking::Max => scores.into_iter().max_by(|a, b| a.0.total_cmp(&b.0)),
+            Ranking::Min => scores.into_iter().min_by(|a, b| a.0.total_cmp(&b.0)),
+        };
+        winner.map(|(_, reindeer)| reindeer).ok_or(StatusCode::BAD_REQUEST)
+    }
+}
+
+fn default_categories() -> Vec<ContestCategory> {
+    vec![
+        ContestCategory::new("fastest", "speed", "Speeding past the finish line with a strength of {{strength}} is {{name}}"),
+        ContestCategory::new("tallest", "height", "{{name}} is standing tall with his {{antler_width}} cm wide antlers"),
+        ContestCategory::new("magician", "snow_magic_power", "{{name}} could blast you away with a snow magic power of {{snow_magic_power}}"),
+        ContestCategory::new("consumer", "cAnD13s_3ATeN-yesT3rdAy", "{{name}} ate lots of candies, but also some {{favorite_food}}"),
+    ]
 }
 
 #[derive(Deserialize, Debug)]
@@ -50,7 +132,7 @@ struct Reindeer {
     strength: i32,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 struct ContestReindeer {
     name: String,
     strength: i32,
@@ -64,20 +146,31 @@ struct ContestReindeer {
 }
 
 #[derive(Serialize, Debug, Eq, PartialEq)]
-struct ContestResult {
-    fastest: String,
-    tallest: String,
-    magician: String,
-    consumer: String,
-}
+#[serde(transparent)]
+struct ContestResult(BTreeMap<String, String>);
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
     use std::ops::Deref;
+    use std::sync::Arc;
 
+    use axum::extract::State;
+    use axum::http::StatusCode;
     use axum::Json;
+    use handlebars::{no_escape, Handlebars};
+    use serde_json::json;
 
-    use crate::day_04::ContestResult;
+    use crate::day_04::{ContestResult, CustomContest, Day04State};
+
+    fn state() -> State<Day04State> {
+        let mut hbs = Handlebars::new();
+        hbs.register_escape_fn(no_escape);
+        State(Day04State {
+            template_engine: Arc::new(hbs),
+            categories: Arc::new(super::default_categories()),
+        })
+    }
 
     #[tokio::test]
     async fn test_day04_post() {
@@ -96,7 +189,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_day04_post_contest() {
-        let result: Json<ContestResult> = super::day04_post_contest(axum::Json(vec![
+        let result: Json<ContestResult> = super::day04_post_contest(state(), axum::Json(vec![
             super::ContestReindeer {
                 name: "Dasher".to_string(),
                 strength: 5,
@@ -118,14 +211,45 @@ mod tests {
                 candies_eaten_yesterday: 5,
             }])).await.expect("Should be ok");
 
-        let expected = ContestResult {
-            fastest: "Speeding past the finish line with a strength of 5 is Dasher".to_string(),
-            tallest: "Dasher is standing tall with his 36 cm wide antlers".to_string(),
-            magician: "Dasher could blast you away with a snow magic power of 9001".to_string(),
-            consumer: "Dancer ate lots of candies, but also some grass".to_string(),
-        };
+        let expected = ContestResult(BTreeMap::from([
+            ("fastest".to_string(), "Speeding past the finish line with a strength of 5 is Dasher".to_string()),
+            ("tallest".to_string(), "Dasher is standing tall with his 36 cm wide antlers".to_string()),
+            ("magician".to_string(), "Dasher could blast you away with a snow magic power of 9001".to_string()),
+            ("consumer".to_string(), "Dancer ate lots of candies, but also some grass".to_string()),
+        ]));
 
         let result_object = result.deref();
         assert_eq!(result_object, &expected);
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_day04_post_contest_custom() {
+        let contest: CustomContest = serde_json::from_value(json!({
+            "reindeers": [
+                { "name": "Dasher", "strength": 5, "antler_width": 36 },
+                { "name": "Dancer", "strength": 6, "antler_width": 37 }
+            ],
+            "categories": [
+                { "name": "strongest", "field": "strength", "template": "{{name}} ist am stärksten ({{strength}})" },
+                { "name": "narrowest", "field": "antler_width", "ranking": "min", "template": "{{name}} has {{antler_width}} cm <narrow> antlers" }
+            ]
+        })).unwrap();
+        let result = super::day04_post_contest_custom(state(), Json(contest)).await.expect("Should be ok");
+
+        assert_eq!(result.0, ContestResult(BTreeMap::from([
+            ("strongest".to_string(), "Dancer ist am stärksten (6)".to_string()),
+            ("narrowest".to_string(), "Dasher has 36 cm <narrow> antlers".to_string()),
+        ])));
+    }
+
+    #[tokio::test]
+    async fn test_day04_post_contest_custom_missing_field() {
+        let contest: CustomContest = serde_json::from_value(json!({
+            "reindeers": [{ "name": "Dasher" }],
+            "categories": [{ "name": "strongest", "field": "strength", "template": "{{name}}" }]
+        })).unwrap();
+        let result = super::day04_post_contest_custom(state(), Json(contest)).await;
+
+        assert_eq!(result.err(), Some(StatusCode::BAD_REQUEST));
+    }
+}
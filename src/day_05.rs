@@ -1,66 +1,158 @@
-use std::fmt;
-use std::str::FromStr;
-use axum::extract::{Query};
-use axum::http::StatusCode;
-use axum::{Json, Router};
+use axum::body::Body;
+use axum::extract::{FromRequest, OriginalUri, Query, Request};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::{BoxError, Json, Router};
+use axum::response::{IntoResponse, Response};
 use axum::routing::{post};
-use serde::{de, Deserialize, Deserializer};
+use futures::{stream, Stream, StreamExt};
 use tracing::info;
+use crate::pagination::{self, PageParams};
+
+const NDJSON: &str = "application/x-ndjson";
+const MAX_LINE_BYTES: usize = 64 * 1024;
 
 pub fn router() -> Router {
-    Router::new().route("/", post(day05_slice))
+    Router::new().route("/", post(day05_post))
 }
 
-#[derive(Debug, Deserialize)]
-#[allow(dead_code)]
-struct Params {
-    #[serde(default, deserialize_with = "empty_string_as_none")]
-    offset: Option<usize>,
-    #[serde(default, deserialize_with = "empty_string_as_none")]
-    limit: Option<usize>,
-    #[serde(default, deserialize_with = "empty_string_as_none")]
-    split: Option<usize>,
+async fn day05_post(uri: OriginalUri, params: Query<PageParams>, request: Request) -> Response {
+    let ndjson = request.headers().get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .map(|content_type| content_type.starts_with(NDJSON))
+        .unwrap_or(false);
+    if ndjson {
+        return day05_slice_stream(params, request.into_body()).into_response();
+    }
+    match Json::<Vec<String>>::from_request(request, &()).await {
+        Ok(strings) => day05_slice(uri, params, strings).await.into_response(),
+        Err(rejection) => rejection.into_response(),
+    }
 }
 
-fn empty_string_as_none<'de, D, T>(de: D) -> Result<Option<T>, D::Error>
-    where
-        D: Deserializer<'de>,
-        T: FromStr,
-        T::Err: fmt::Display,
-{
-    let opt = Option::<String>::deserialize(de)?;
-    match opt.as_deref() {
-        None | Some("") => Ok(None),
-        Some(s) => FromStr::from_str(s).map_err(de::Error::custom).map(Some),
-    }
+async fn day05_slice(OriginalUri(uri): OriginalUri, Query(params): Query<PageParams>, Json(strings): Json<Vec<String>>) -> Result<(HeaderMap, String), StatusCode> {
+    let page = params.paginate(strings.len())?;
+    info!("Slice called with {:?} for {:?}", page, &strings);
+    let headers = page.headers(&uri);
+    let result = page.slice(&strings);
+    let body = match page.split {
+        Some(split) => serde_json::to_string(&pagination::split(result, split)),
+        None => serde_json::to_string(result),
+    };
+    body.map(|body| (headers, body)).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
 }
 
-async fn day05_slice(params: Query<Params>, Json(strings): Json<Vec<String>>) -> Result<String, StatusCode> {
-    if strings.is_empty() {
-        return Ok("[]".into());
-    }
-    let offset = params.offset.unwrap_or(0);
-    let limit = params.limit.unwrap_or(strings.len());
-    let split = params.split;
-    info!("Slice called with offset: {}, limit: {}, split: {:?} for {:?}", offset, limit, split, &strings);
-    let mut end = offset + limit;
-    if end > strings.len() {
-        end = strings.len();
-    }
-    let result = &strings[offset..end];
-    if split.is_some() {
-        let split = split.unwrap();
-        let mut split_result = Vec::new();
-        let mut index = 0;
-        while index < result.len() {
-            let mut end = index + split;
-            if end > result.len() {
-                end = result.len();
+fn day05_slice_stream(Query(params): Query<PageParams>, body: Body) -> Result<Response, StatusCode> {
+    let (offset, limit) = params.paginate_unbounded()?;
+    info!("Streaming slice called with offset: {}, limit: {:?}, split: {:?}", offset, limit, params.split);
+    let names = ndjson_lines(body)
+        .map(|line| line.and_then(|line| serde_json::from_str::<String>(&line).map_err(BoxError::from)))
+        .skip(offset)
+        .take(limit.unwrap_or(usize::MAX));
+    let chunks = match params.split {
+        Some(split) => names.chunks(split)
+            .map(|group| group.into_iter().collect::<Result<Vec<_>, _>>()
+                .and_then(|group| serde_json::to_string(&group).map_err(BoxError::from)))
+            .boxed(),
+        None => names
+            .map(|name| name.and_then(|name| serde_json::to_string(&name).map_err(BoxError::from)))
+            .boxed(),
+    };
+    let lines = chunks.map(|line| line.map(|line| line + "\n"));
+    Ok(([(header::CONTENT_TYPE, HeaderValue::from_static(NDJSON))], Body::from_stream(lines)).into_response())
+}
+
+/// Splits the request body into trimmed, non-empty lines without buffering more than the current line,
+/// lines longer than `MAX_LINE_BYTES` end the stream with an error.
+fn ndjson_lines(body: Body) -> impl Stream<Item = Result<String, BoxError>> {
+    let state = (body.into_data_stream(), Vec::new(), 0, false);
+    stream::unfold(state, |(mut data, mut buffer, mut scanned, mut finished)| async move {
+        loop {
+            let line_end = buffer[scanned..].iter().position(|byte| *byte == b'\n').map(|position| scanned + position);
+            scanned = buffer.len();
+            if line_end.unwrap_or(buffer.len()) > MAX_LINE_BYTES {
+                let error = format!("line is longer than {} bytes", MAX_LINE_BYTES);
+                return Some((Err(error.into()), (data, Vec::new(), 0, true)));
+            }
+            let line: Option<Vec<u8>> = match line_end {
+                Some(position) => Some(buffer.drain(..=position).collect()),
+                None if finished && !buffer.is_empty() => Some(std::mem::take(&mut buffer)),
+                None if finished => return None,
+                None => None,
+            };
+            if let Some(line) = line {
+                scanned = 0;
+                match String::from_utf8(line) {
+                    Ok(line) if line.trim().is_empty() => continue,
+                    Ok(line) => return Some((Ok(line.trim().to_string()), (data, buffer, scanned, finished))),
+                    Err(e) => return Some((Err(e.into()), (data, Vec::new(), 0, true))),
+                }
+            }
+            match data.next().await {
+                Some(Ok(chunk)) => buffer.extend_from_slice(&chunk),
+                Some(Err(e)) => return Some((Err(e.into()), (data, Vec::new(), 0, true))),
+                None => finished = true,
             }
-            split_result.push(Vec::from(&result[index..end]));
-            index += split;
         }
-        return Ok(serde_json::to_string(&split_result).unwrap());
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::{to_bytes, Body};
+    use axum::extract::{OriginalUri, Query};
+    use axum::http::{StatusCode, Uri};
+    use axum::Json;
+    use futures::{stream, StreamExt};
+    use std::convert::Infallible;
+    use crate::pagination::PageParams;
+
+    fn names() -> Json<Vec<String>> {
+        Json(["Ava", "Caleb", "Mia", "Owen", "Lily", "Ethan"].iter().map(|name| name.to_string()).collect())
+    }
+
+    fn query(uri: &Uri) -> Query<PageParams> {
+        Query::try_from_uri(uri).unwrap()
+    }
+
+    async fn slice(query: &'static str) -> Result<String, StatusCode> {
+        let uri = Uri::from_static(query);
+        super::day05_slice(OriginalUri(uri.clone()), self::query(&uri), names()).await.map(|(_, body)| body)
+    }
+
+    async fn slice_stream(query: &'static str) -> Result<String, StatusCode> {
+        let body = Body::from("\"Ava\"\n\"Caleb\"\n\n\"Mia\"\n\"Owen\"\n\"Lily\"\n\"Ethan\"");
+        let response = super::day05_slice_stream(self::query(&Uri::from_static(query)), body)?;
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        Ok(String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_day05_slice() {
+        assert_eq!(slice("/5?offset=3&limit=2").await, Ok("[\"Owen\",\"Lily\"]".to_string()));
+        assert_eq!(slice("/5?offset=1&limit=4&split=2").await, Ok("[[\"Caleb\",\"Mia\"],[\"Owen\",\"Lily\"]]".to_string()));
+        assert_eq!(slice("/5?offset=-1").await, Ok("[\"Ethan\"]".to_string()));
     }
-    Ok(serde_json::to_string(&result.to_vec()).unwrap())
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_day05_slice_invalid() {
+        assert_eq!(slice("/5?offset=10").await, Ok("[]".to_string()));
+        assert_eq!(slice("/5?split=0").await, Err(StatusCode::BAD_REQUEST));
+    }
+
+    #[tokio::test]
+    async fn test_day05_slice_stream() {
+        assert_eq!(slice_stream("/5?offset=1&limit=3").await, Ok("\"Caleb\"\n\"Mia\"\n\"Owen\"\n".to_string()));
+        assert_eq!(slice_stream("/5?offset=1&limit=4&split=3").await, Ok("[\"Caleb\",\"Mia\",\"Owen\"]\n[\"Lily\"]\n".to_string()));
+        assert_eq!(slice_stream("/5?offset=-1").await, Err(StatusCode::BAD_REQUEST));
+    }
+
+    #[tokio::test]
+    async fn test_ndjson_lines_too_long() {
+        let chunks = ["\"Ava\"\n\"".to_string(), "a".repeat(super::MAX_LINE_BYTES), "\"\n\"Mia\"".to_string()];
+        let body = Body::from_stream(stream::iter(chunks.map(Ok::<_, Infallible>)));
+        let lines: Vec<_> = super::ndjson_lines(body).collect().await;
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].as_ref().unwrap(), "\"Ava\"");
+        assert_eq!(lines[1].as_ref().unwrap_err().to_string(), format!("line is longer than {} bytes", super::MAX_LINE_BYTES));
+    }
+}
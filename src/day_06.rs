@@ -1,33 +1,156 @@
-use axum::http::StatusCode;
+use std::io::Read;
+use std::sync::Arc;
+use axum::body::Bytes;
+use axum::extract::{FromRequest, Request, State};
+use axum::http::{header, StatusCode};
 use axum::Json;
 use axum::routing::post;
-use serde::Serialize;
-use tracing::info;
+use axum_extra::extract::Multipart;
+use serde::{Deserialize, Serialize};
+use tar::Archive;
+use tracing::{error, info};
+use crate::phrase_counter::{MatchOptions, Phrase, PhraseCounter, PhraseCounts};
+
+const MAX_DOCUMENTS: usize = 100;
+const MAX_DOCUMENTS_BYTES: usize = 1024 * 1024;
+
+#[derive(Clone)]
+struct Day06State {
+    elf_counter: Arc<PhraseCounter>,
+}
 
 pub fn router() -> axum::Router {
+    let shared_state = Day06State {
+        elf_counter: Arc::new(PhraseCounter::elf_counter()),
+    };
+
     axum::Router::new()
         .route("/", post(day06_post))
+        .route("/count", post(day06_count))
+        .route("/documents", post(day06_documents))
+        .with_state(shared_state)
 }
 
-async fn day06_post(text: String) -> Result<Json<Answer>, StatusCode> {
+async fn day06_post(State(state): State<Day06State>, text: String) -> Result<Json<Answer>, StatusCode> {
     info!("Got text: {}", text);
-    let elfs: i32 = text.split(" ").filter(|word| word.contains(&"elf")).count() as i32;
-    let mut filter_text = text.clone();
-    let mut elf_shelf = 0;
-    while filter_text.find("elf on a shelf").is_some() {
-        filter_text = filter_text.replacen("elf on a shelf", "elf", 1);
-        elf_shelf += 1;
+    Ok(Json(Answer::from(state.elf_counter.count(&text))))
+}
+
+#[derive(Deserialize, Debug)]
+struct CountRequest {
+    text: String,
+    phrases: Vec<Phrase>,
+    #[serde(default)]
+    options: MatchOptions,
+}
+
+async fn day06_count(Json(request): Json<CountRequest>) -> Result<Json<PhraseCounts>, (StatusCode, String)> {
+    info!("Count phrases {:?} with {:?}", request.phrases, request.options);
+    let counter = PhraseCounter::new(request.phrases, request.options)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    Ok(Json(counter.count(&request.text)))
+}
+
+#[derive(Serialize, Debug, Default, Eq, PartialEq)]
+struct DocumentsAnswer {
+    documents: Vec<DocumentAnswer>,
+    total: Answer,
+}
+
+#[derive(Serialize, Debug, Eq, PartialEq)]
+struct DocumentAnswer {
+    name: String,
+    #[serde(flatten)]
+    answer: Answer,
+}
+
+impl DocumentsAnswer {
+    fn check_documents(&self) -> Result<(), StatusCode> {
+        if self.documents.len() >= MAX_DOCUMENTS {
+            error!("More than {} documents uploaded", MAX_DOCUMENTS);
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+        Ok(())
     }
-    let shelf_no_elf: i32 = filter_text.split(" ").filter(|word| word.contains(&"shelf")).count() as i32;
-    let answer = Answer {
-        elf: elfs,
-        elf_shelf,
-        shelf_no_elf,
-    };
-    Ok(Json(answer))
+
+    fn push(&mut self, name: String, answer: Answer) {
+        self.total.elf += answer.elf;
+        self.total.elf_shelf += answer.elf_shelf;
+        self.total.shelf_no_elf += answer.shelf_no_elf;
+        self.documents.push(DocumentAnswer { name, answer });
+    }
+}
+
+async fn day06_documents(State(state): State<Day06State>, request: Request) -> Result<Json<DocumentsAnswer>, StatusCode> {
+    let content_type = request.headers().get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+    info!("Count documents of type {}", content_type);
+    if content_type.starts_with("multipart/form-data") {
+        let multipart = Multipart::from_request(request, &()).await.map_err(|_| StatusCode::BAD_REQUEST)?;
+        count_multipart(&state.elf_counter, multipart).await.map(Json)
+    } else if content_type.starts_with("application/x-tar") {
+        let archive = Bytes::from_request(request, &()).await.map_err(|_| StatusCode::BAD_REQUEST)?;
+        count_archive(&state.elf_counter, &archive).map(Json)
+    } else {
+        Err(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+    }
+}
+
+async fn count_multipart(counter: &PhraseCounter, mut multipart: Multipart) -> Result<DocumentsAnswer, StatusCode> {
+    let mut answer = DocumentsAnswer::default();
+    let mut bytes = 0;
+    while let Some(mut field) = multipart.next_field().await.map_err(|_| StatusCode::BAD_REQUEST)? {
+        answer.check_documents()?;
+        let name = field.file_name().or(field.name()).unwrap_or("").to_string();
+        let mut scanner = counter.scanner(false);
+        while let Some(chunk) = field.chunk().await.map_err(|_| StatusCode::BAD_REQUEST)? {
+            bytes = check_bytes(bytes, chunk.len())?;
+            scanner.feed(&chunk);
+        }
+        answer.push(name, Answer::from(scanner.finish()));
+    }
+    Ok(answer)
 }
 
-#[derive(Serialize, Debug)]
+fn count_archive(counter: &PhraseCounter, archive: &[u8]) -> Result<DocumentsAnswer, StatusCode> {
+    let mut answer = DocumentsAnswer::default();
+    let mut archive = Archive::new(archive);
+    let entries = archive.entries().map_err(|_| StatusCode::BAD_REQUEST)?;
+    let mut chunk = [0u8; 8192];
+    let mut bytes = 0;
+    for entry in entries {
+        let mut entry = entry.map_err(|e| { error!("Could not read archive entry: {}", e); StatusCode::BAD_REQUEST })?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        answer.check_documents()?;
+        let name = entry.path().map(|path| path.to_string_lossy().to_string()).unwrap_or_default();
+        let mut scanner = counter.scanner(false);
+        loop {
+            let read = entry.read(&mut chunk).map_err(|_| StatusCode::BAD_REQUEST)?;
+            if read == 0 {
+                break;
+            }
+            bytes = check_bytes(bytes, read)?;
+            scanner.feed(&chunk[..read]);
+        }
+        answer.push(name, Answer::from(scanner.finish()));
+    }
+    Ok(answer)
+}
+
+fn check_bytes(bytes: usize, read: usize) -> Result<usize, StatusCode> {
+    let bytes = bytes + read;
+    if bytes > MAX_DOCUMENTS_BYTES {
+        error!("Documents have more than {} bytes", MAX_DOCUMENTS_BYTES);
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+    Ok(bytes)
+}
+
+#[derive(Serialize, Debug, Default, Eq, PartialEq)]
 struct Answer {
     pub elf: i32,
     #[serde(rename = "elf on a shelf")]
@@ -36,14 +159,34 @@ struct Answer {
     pub shelf_no_elf: i32,
 }
 
+impl From<PhraseCounts> for Answer {
+    fn from(counts: PhraseCounts) -> Answer {
+        let count = |name: &str| counts.counts.get(name).copied().unwrap_or(0) as i32;
+        Answer {
+            elf: count("elf"),
+            elf_shelf: count("elf on a shelf"),
+            shelf_no_elf: count("shelf with no elf on it"),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::ops::Deref;
-    use crate::day_06::Answer;
+    use std::sync::Arc;
+    use axum::extract::State;
+    use axum::http::StatusCode;
+    use axum::Json;
+    use crate::day_06::{Answer, CountRequest, Day06State};
+    use crate::phrase_counter::{MatchOptions, Phrase, PhraseCounter};
+
+    fn state() -> State<Day06State> {
+        State(Day06State { elf_counter: Arc::new(PhraseCounter::elf_counter()) })
+    }
 
     #[tokio::test]
     pub async fn test_day06_post() {
-        let answer = super::day06_post("The mischievous elf peeked out from behind the toy workshop,
+        let answer = super::day06_post(state(), "The mischievous elf peeked out from behind the toy workshop,
                              and another elf joined in the festive dance.
                              Look, there is also an elf on that shelf!".to_string()).await.expect("Should be ok");
         let a: &Answer = answer.deref();
@@ -53,7 +196,7 @@ mod tests {
 
     #[tokio::test]
     pub async fn test_day06_contest_post() {
-        let answer = super::day06_post("there is an elf on a shelf on an elf.
+        let answer = super::day06_post(state(), "there is an elf on a shelf on an elf.
                                                     there is also another shelf in Belfast.".to_string()
                                                     ).await.expect("Should be ok");
         let a: &Answer = answer.deref();
@@ -65,7 +208,7 @@ mod tests {
 
     #[tokio::test]
     pub async fn test_day06_contest_post2() {
-        let answer = super::day06_post("In Belfast I heard an elf on a shelf on a shelf on a".to_string()
+        let answer = super::day06_post(state(), "In Belfast I heard an elf on a shelf on a shelf on a".to_string()
                                                     ).await.expect("Should be ok");
         let a: &Answer = answer.deref();
 
@@ -73,4 +216,68 @@ mod tests {
         assert_eq!(a.elf_shelf, 2);
         assert_eq!(a.shelf_no_elf, 0);
     }
+
+    #[tokio::test]
+    pub async fn test_day06_count() {
+        let request = CountRequest {
+            text: "Santa's Sleigh, santa's sleigh\nand a sleigh bell".to_string(),
+            phrases: vec![Phrase::new("santa", "santa's sleigh", &[]), Phrase::new("sleigh", "sleigh", &["santa"])],
+            options: MatchOptions { case_insensitive: true, ..Default::default() },
+        };
+        let counts = super::day06_count(Json(request)).await.expect("Should be ok");
+
+        assert_eq!(counts.counts["santa"], 2);
+        assert_eq!(counts.counts["sleigh"], 1);
+        assert_eq!(counts.matches["sleigh"], vec![(37, 43)]);
+    }
+
+    #[tokio::test]
+    pub async fn test_day06_count_invalid() {
+        let request = CountRequest {
+            text: "elf".to_string(),
+            phrases: vec![Phrase::new("elf", "elf", &["missing"])],
+            options: MatchOptions::default(),
+        };
+        let result = super::day06_count(Json(request)).await;
+
+        assert_eq!(result.err().map(|(status, _)| status), Some(StatusCode::BAD_REQUEST));
+    }
+
+    #[test]
+    pub fn test_day06_count_archive() {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, text) in [("a.txt", "an elf on a shelf"), ("b.txt", "a shelf in Belfast")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(text.len() as u64);
+            header.set_cksum();
+            builder.append_data(&mut header, name, text.as_bytes()).unwrap();
+        }
+        let archive = builder.into_inner().unwrap();
+        let answer = super::count_archive(&PhraseCounter::elf_counter(), &archive).expect("Should be ok");
+
+        assert_eq!(answer.documents.len(), 2);
+        assert_eq!(answer.documents[0].name, "a.txt");
+        assert_eq!(answer.documents[0].answer, Answer { elf: 2, elf_shelf: 1, shelf_no_elf: 0 });
+        assert_eq!(answer.documents[1].answer, Answer { elf: 2, elf_shelf: 0, shelf_no_elf: 1 });
+        assert_eq!(answer.total, Answer { elf: 4, elf_shelf: 1, shelf_no_elf: 1 });
+    }
+
+    #[test]
+    pub fn test_day06_count_archive_limits() {
+        let archive = |documents: usize, size: usize| {
+            let mut builder = tar::Builder::new(Vec::new());
+            for index in 0..documents {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(size as u64);
+                header.set_cksum();
+                builder.append_data(&mut header, format!("{}.txt", index), vec![b'e'; size].as_slice()).unwrap();
+            }
+            builder.into_inner().unwrap()
+        };
+        let counter = PhraseCounter::elf_counter();
+
+        assert!(super::count_archive(&counter, &archive(super::MAX_DOCUMENTS, 1)).is_ok());
+        assert_eq!(super::count_archive(&counter, &archive(super::MAX_DOCUMENTS + 1, 1)).err(), Some(StatusCode::PAYLOAD_TOO_LARGE));
+        assert_eq!(super::count_archive(&counter, &archive(2, super::MAX_DOCUMENTS_BYTES / 2 + 1)).err(), Some(StatusCode::PAYLOAD_TOO_LARGE));
+    }
 }
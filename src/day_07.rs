@@ -1,81 +1,533 @@
 use std::collections::HashMap;
-use axum::http::StatusCode;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use axum::extract::{Query, State};
+use axum::http::{header, StatusCode};
 use axum::Json;
-use axum::routing::{get};
+use axum::routing::{get, post};
 use axum_extra::headers::Cookie;
 use axum_extra::TypedHeader;
 use lib_base64::Base64;
 use serde::{Deserialize, Serialize};
 use tracing::{error, info};
+use crate::recipe_encoding::{self, Encoding};
+use crate::signed_cookie::CookieSigner;
+use crate::units::{convert, Amount};
+
+#[derive(Clone)]
+struct Day07State {
+    signer: Arc<CookieSigner>,
+}
 
 pub fn router() -> axum::Router {
+    info!("Initializing state.");
+    let shared_state = Day07State {
+        signer: Arc::new(CookieSigner::from_env()),
+    };
+
     axum::Router::new()
-        .route("/decode", get(day07_get))
+        .route("/decode", get(day07_get).post(day07_post_decode))
         .route("/bake", get(day07_get_task2))
+        .route("/plan", post(day07_plan))
+        .route("/cookie", post(day07_issue_cookie))
+        .with_state(shared_state)
 }
 
-async fn day07_get(TypedHeader(cookie): TypedHeader<Cookie>) -> Result<String,StatusCode> {
-    match cookie.get("recipe") {
-        Some(recipe) => {
-            recipe.to_string().decode().map_err(|_| StatusCode::BAD_REQUEST)
-        }
-        None => Err(StatusCode::BAD_REQUEST)
+#[derive(Deserialize, Debug, Default)]
+struct DecodeParams {
+    #[serde(default)]
+    encoding: Encoding,
+    recipe: Option<String>,
+}
+
+fn decode_recipe(signer: &CookieSigner, recipe: &str, encoding: Encoding) -> Result<String, (StatusCode, String)> {
+    let recipe = signer.verify(recipe)?;
+    recipe_encoding::decode(recipe, encoding).map_err(|e| (StatusCode::BAD_REQUEST, e))
+}
+
+fn recipe_cookie(signer: &CookieSigner, cookie: Option<&Cookie>, encoding: Encoding) -> Result<String, (StatusCode, String)> {
+    let recipe = cookie.and_then(|cookie| cookie.get("recipe"))
+        .ok_or((StatusCode::BAD_REQUEST, "missing recipe cookie".to_string()))?;
+    decode_recipe(signer, recipe, encoding)
+}
+
+async fn day07_get(State(state): State<Day07State>, Query(params): Query<DecodeParams>, cookie: Option<TypedHeader<Cookie>>) -> Result<String, (StatusCode, String)> {
+    match &params.recipe {
+        Some(recipe) => decode_recipe(&state.signer, recipe, params.encoding),
+        None => recipe_cookie(&state.signer, cookie.as_ref().map(|TypedHeader(cookie)| cookie), params.encoding),
     }
 }
 
+async fn day07_post_decode(State(state): State<Day07State>, Query(params): Query<DecodeParams>, recipe: String) -> Result<String, (StatusCode, String)> {
+    decode_recipe(&state.signer, &recipe, params.encoding)
+}
+
+async fn day07_issue_cookie(State(state): State<Day07State>, Json(recipe): Json<serde_json::Value>) -> Result<([(header::HeaderName, String); 1], StatusCode), StatusCode> {
+    info!("Issue cookie for {}", recipe);
+    let encoded = recipe.to_string().encode().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let cookie = format!("recipe={}; Path=/7; HttpOnly; SameSite=Strict", state.signer.sign(&encoded));
+    Ok(([(header::SET_COOKIE, cookie)], StatusCode::CREATED))
+}
+
 #[derive(Deserialize, Debug)]
 struct BakeData{
     recipe: HashMap<String, i64>,
     pantry: HashMap<String, i64>,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, Eq, PartialEq)]
 struct BakeResult{
-    cookies: i64,
+    cookies: u64,
     pantry: HashMap<String, i64>,
 }
 
 impl BakeData {
-    fn ingredients_available(&mut self) -> bool {
-        let available = self.recipe.iter()
-            .filter(|(ingredient, amount)|
-                self.pantry.contains_key(ingredient as &str)
-                    && self.pantry.get(ingredient as &str).unwrap() >= amount)
-            .count() == self.recipe.len();
-        if available {
-            self.recipe.iter()
-                .for_each(|(ingredient, amount)| {
-                let current_amount = self.pantry.get(ingredient).unwrap();
-                self.pantry.insert(ingredient.to_string(), current_amount - amount);
-            });
-            true
-        } else {
-            false
+    fn max_cookies(&self) -> u64 {
+        self.recipe.iter()
+            .filter(|(_, amount)| **amount > 0)
+            .map(|(ingredient, amount)| {
+                let available = self.pantry.get(ingredient).copied().unwrap_or(0).max(0) as u64;
+                available / *amount as u64
+            })
+            .min()
+            .unwrap_or(0)
+    }
+
+    fn use_ingredients(&mut self, cookies: u64) {
+        for (ingredient, amount) in self.recipe.iter().filter(|(_, amount)| **amount > 0) {
+            if let Some(available) = self.pantry.get_mut(ingredient) {
+                let used = cookies as u128 * *amount as u128;
+                *available = (*available as i128 - used as i128) as i64;
+            }
         }
     }
 
     fn bake(&mut self) -> BakeResult {
-        let mut cookie_counter = 0;
         self.recipe.retain(|_, amount| *amount > 0i64);
         info!("Recipe after cleanup: {:?}", self.recipe);
-        while self.ingredients_available() {
-            cookie_counter += 1;
-        }
+        let cookies = self.max_cookies();
+        self.use_ingredients(cookies);
         BakeResult {
-            cookies: cookie_counter,
+            cookies,
             pantry: self.pantry.clone(),
         }
     }
 }
 
-async fn day07_get_task2(TypedHeader(cookie): TypedHeader<Cookie>) -> Result<Json<BakeResult>,StatusCode> {
+/// Bake request whose amounts may carry units like `"1.5kg"` or `{"amount": 2, "unit": "cup"}`.
+/// Densities in grams per millilitre allow recipes in volume for pantries in mass and vice versa.
+#[derive(Deserialize, Debug)]
+struct UnitBakeData {
+    recipe: HashMap<String, Amount>,
+    pantry: HashMap<String, Amount>,
+    #[serde(default)]
+    densities: HashMap<String, f64>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct UnitBakeResult {
+    cookies: u64,
+    pantry: HashMap<String, Amount>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(untagged)]
+enum BakeResponse {
+    Plain(BakeResult),
+    Units(UnitBakeResult),
+}
+
+/// Amounts are baked in thousandths of the base units so fractions of a unit are not lost.
+const UNIT_SCALE: f64 = 1000.0;
+
+fn to_scaled(value: f64) -> Result<i64, String> {
+    let scaled = (value * UNIT_SCALE).round();
+    if scaled.is_finite() && scaled.abs() < i64::MAX as f64 {
+        Ok(scaled as i64)
+    } else {
+        Err(format!("amount {} is out of range", value))
+    }
+}
+
+impl UnitBakeData {
+    fn is_plain(&self) -> bool {
+        self.recipe.values().chain(self.pantry.values()).all(Amount::is_plain)
+    }
+
+    fn into_bake_data(self) -> BakeData {
+        let plain = |amounts: HashMap<String, Amount>| amounts.into_iter()
+            .filter_map(|(ingredient, amount)| match amount {
+                Amount::Plain(amount) => Some((ingredient, amount)),
+                _ => None,
+            })
+            .collect();
+        BakeData { recipe: plain(self.recipe), pantry: plain(self.pantry) }
+    }
+
+    /// Normalizes every recipe amount to the unit of the same ingredient in the pantry, bakes,
+    /// and reports the leftovers in the units the pantry was given in.
+    fn bake(&self) -> Result<UnitBakeResult, String> {
+        let mut pantry = HashMap::new();
+        let mut pantry_units = HashMap::new();
+        for (ingredient, amount) in &self.pantry {
+            let (value, unit) = amount.quantity()?;
+            pantry.insert(ingredient.clone(), to_scaled(value * unit.factor)?);
+            pantry_units.insert(ingredient, unit);
+        }
+        let mut recipe = HashMap::new();
+        for (ingredient, amount) in &self.recipe {
+            let (value, unit) = amount.quantity()?;
+            let value = match pantry_units.get(ingredient) {
+                Some(target) => convert(value * unit.factor, unit.dimension, target.dimension, self.densities.get(ingredient).copied())
+                    .map_err(|e| format!("{} for {}", e, ingredient))?,
+                None => value * unit.factor,
+            };
+            recipe.insert(ingredient.clone(), to_scaled(value)?);
+        }
+        let result = BakeData { recipe, pantry }.bake();
+        let pantry = self.pantry.iter()
+            .map(|(ingredient, amount)| {
+                let unit = pantry_units[ingredient];
+                let left = result.pantry[ingredient] as f64 / UNIT_SCALE / unit.factor;
+                (ingredient.clone(), amount.with_value(left))
+            })
+            .collect();
+        Ok(UnitBakeResult { cookies: result.cookies, pantry })
+    }
+}
+
+async fn day07_get_task2(State(state): State<Day07State>, TypedHeader(cookie): TypedHeader<Cookie>) -> Result<Json<BakeResponse>, (StatusCode, String)> {
     info!("Got cookie: {:?}", cookie);
-    let data = cookie.get("recipe")
-        .and_then(|data| Some(data.to_string().decode())).ok_or(StatusCode::BAD_REQUEST)?.map_err(|_| StatusCode::BAD_REQUEST);
+    let data = recipe_cookie(&state.signer, Some(&cookie), Encoding::Auto)?;
     info!("Got data: {:?}", data);
-    let mut bake_data: BakeData = serde_json::from_str(&data.unwrap()).map_err(|e| { error!("Could not parse data: {}", e); StatusCode::BAD_REQUEST})?;
+    let bake_data: UnitBakeData = serde_json::from_str(&data).map_err(|e| { error!("Could not parse data: {}", e); (StatusCode::BAD_REQUEST, "recipe is not valid bake data".to_string())})?;
     info!("Got bake data: {:?}", bake_data);
-    let bake_result = bake_data.bake();
+    let bake_result = if bake_data.is_plain() {
+        BakeResponse::Plain(bake_data.into_bake_data().bake())
+    } else {
+        BakeResponse::Units(bake_data.bake().map_err(|e| (StatusCode::BAD_REQUEST, e))?)
+    };
     info!("Bake result: {:?}", bake_result);
     Ok(Json(bake_result))
 }
+
+#[derive(Deserialize, Debug)]
+struct PlanRecipe {
+    value: u64,
+    ingredients: HashMap<String, i64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PlanRequest {
+    recipes: HashMap<String, PlanRecipe>,
+    pantry: HashMap<String, i64>,
+    #[serde(default = "default_time_limit_ms")]
+    time_limit_ms: u64,
+}
+
+fn default_time_limit_ms() -> u64 {
+    1000
+}
+
+/// Upper bound for `time_limit_ms`, so a single request cannot keep a blocking thread busy.
+const MAX_PLAN_MS: u64 = 5000;
+
+#[derive(Serialize, Debug, Eq, PartialEq)]
+struct PlanResult {
+    cookies: HashMap<String, u64>,
+    value: u128,
+    pantry: HashMap<String, i64>,
+    bottleneck: Option<String>,
+    optimal: bool,
+}
+
+async fn day07_plan(Json(request): Json<PlanRequest>) -> Result<Json<PlanResult>, StatusCode> {
+    info!("Plan called with {:?}", request);
+    if request.recipes.values().any(|recipe| !recipe.ingredients.values().any(|amount| *amount > 0)) {
+        info!("Rejecting recipe without ingredients.");
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let result = tokio::task::spawn_blocking(move || plan(request))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    info!("Plan result: {:?}", result);
+    Ok(Json(result))
+}
+
+/// Chooses how many of each recipe to bake so that the total value is maximal. Starts from a
+/// greedy allocation and improves it by branch and bound until it is proven optimal or the time
+/// limit is reached.
+fn plan(request: PlanRequest) -> PlanResult {
+    let mut recipes: Vec<(String, PlanRecipe)> = request.recipes.into_iter().collect();
+    recipes.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut planner = Planner {
+        recipes: &recipes,
+        deadline: Instant::now() + Duration::from_millis(request.time_limit_ms.min(MAX_PLAN_MS)),
+        current: vec![0; recipes.len()],
+        best: vec![0; recipes.len()],
+        best_value: 0,
+        timed_out: false,
+    };
+    planner.greedy(&request.pantry);
+    planner.search(0, &mut request.pantry.clone(), 0);
+    let mut pantry = request.pantry;
+    for ((_, recipe), count) in recipes.iter().zip(planner.best.iter()) {
+        let mut data = BakeData { recipe: recipe.ingredients.clone(), pantry };
+        data.use_ingredients(*count);
+        pantry = data.pantry;
+    }
+    PlanResult {
+        cookies: recipes.iter().map(|(name, _)| name.clone()).zip(planner.best.iter().copied()).collect(),
+        value: planner.best_value,
+        bottleneck: bottleneck(&recipes, &pantry),
+        pantry,
+        optimal: !planner.timed_out,
+    }
+}
+
+struct Planner<'a> {
+    recipes: &'a [(String, PlanRecipe)],
+    deadline: Instant,
+    current: Vec<u64>,
+    best: Vec<u64>,
+    best_value: u128,
+    timed_out: bool,
+}
+
+impl Planner<'_> {
+    fn greedy(&mut self, pantry: &HashMap<String, i64>) {
+        let mut order: Vec<usize> = (0..self.recipes.len()).collect();
+        let usage = |index: usize| self.recipes[index].1.ingredients.values().filter(|amount| **amount > 0).map(|amount| *amount as f64).sum::<f64>();
+        order.sort_by(|a, b| {
+            let a_ratio = self.recipes[*a].1.value as f64 / usage(*a);
+            let b_ratio = self.recipes[*b].1.value as f64 / usage(*b);
+            b_ratio.total_cmp(&a_ratio)
+        });
+        let mut pantry = pantry.clone();
+        let mut value = 0;
+        for index in order {
+            let recipe = &self.recipes[index].1;
+            let mut data = BakeData { recipe: recipe.ingredients.clone(), pantry };
+            let cookies = data.max_cookies();
+            data.use_ingredients(cookies);
+            pantry = data.pantry;
+            self.best[index] = cookies;
+            value += cookies as u128 * recipe.value as u128;
+        }
+        self.best_value = value;
+    }
+
+    fn search(&mut self, index: usize, pantry: &mut HashMap<String, i64>, value: u128) {
+        if Instant::now() > self.deadline {
+            self.timed_out = true;
+            return;
+        }
+        if index == self.recipes.len() {
+            if value > self.best_value {
+                self.best_value = value;
+                self.best = self.current.clone();
+            }
+            return;
+        }
+        if value as f64 + self.upper_bound(index, pantry) < self.best_value as f64 + 1.0 {
+            return;
+        }
+        let recipe = &self.recipes[index].1;
+        let mut data = BakeData { recipe: recipe.ingredients.clone(), pantry: pantry.clone() };
+        let max = data.max_cookies();
+        data.use_ingredients(max);
+        let mut remaining = data.pantry;
+        for count in (0..=max).rev() {
+            self.current[index] = count;
+            self.search(index + 1, &mut remaining, value + count as u128 * recipe.value as u128);
+            if self.timed_out {
+                return;
+            }
+            for (ingredient, amount) in recipe.ingredients.iter().filter(|(_, amount)| **amount > 0) {
+                if let Some(available) = remaining.get_mut(ingredient) {
+                    *available += amount;
+                }
+            }
+        }
+        self.current[index] = 0;
+    }
+
+    /// Value the remaining recipes could reach at most, taking the tighter of the bound per recipe
+    /// and the bound per ingredient with all other ingredients relaxed.
+    fn upper_bound(&self, index: usize, pantry: &HashMap<String, i64>) -> f64 {
+        let remaining = &self.recipes[index..];
+        let per_recipe: f64 = remaining.iter()
+            .map(|(_, recipe)| {
+                let data = BakeData { recipe: recipe.ingredients.clone(), pantry: pantry.clone() };
+                data.max_cookies() as f64 * recipe.value as f64
+            })
+            .sum();
+        let per_ingredient = pantry.iter()
+            .filter(|(ingredient, _)| remaining.iter().all(|(_, recipe)| recipe.ingredients.get(*ingredient).map(|amount| *amount > 0).unwrap_or(false)))
+            .map(|(ingredient, available)| {
+                let best_ratio = remaining.iter()
+                    .map(|(_, recipe)| recipe.value as f64 / recipe.ingredients[ingredient] as f64)
+                    .fold(0f64, f64::max);
+                (*available).max(0) as f64 * best_ratio
+            })
+            .fold(f64::INFINITY, f64::min);
+        per_recipe.min(per_ingredient)
+    }
+}
+
+/// The ingredient that prevents baking another cookie of the most recipes.
+fn bottleneck(recipes: &[(String, PlanRecipe)], pantry: &HashMap<String, i64>) -> Option<String> {
+    let mut blocked: HashMap<&String, usize> = HashMap::new();
+    for (_, recipe) in recipes {
+        for (ingredient, amount) in recipe.ingredients.iter().filter(|(_, amount)| **amount > 0) {
+            if pantry.get(ingredient).copied().unwrap_or(0) < *amount {
+                *blocked.entry(ingredient).or_default() += 1;
+            }
+        }
+    }
+    blocked.into_iter()
+        .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(a.0)))
+        .map(|(ingredient, _)| ingredient.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use proptest::collection::hash_map;
+    use proptest::prelude::*;
+    use serde_json::json;
+    use crate::units::Amount;
+    use super::{BakeData, PlanRecipe, PlanRequest, UnitBakeData};
+
+    fn bake_iteratively(recipe: &HashMap<String, i64>, pantry: &HashMap<String, i64>) -> (u64, HashMap<String, i64>) {
+        let recipe: HashMap<&String, &i64> = recipe.iter().filter(|(_, amount)| **amount > 0).collect();
+        let mut pantry = pantry.clone();
+        let mut cookies = 0;
+        while recipe.iter().all(|(ingredient, amount)| pantry.get(*ingredient).map(|available| available >= amount).unwrap_or(false)) {
+            recipe.iter().for_each(|(ingredient, amount)| *pantry.get_mut(*ingredient).unwrap() -= **amount);
+            cookies += 1;
+        }
+        (cookies, pantry)
+    }
+
+    #[test]
+    fn test_bake_large_pantry() {
+        let mut data = BakeData {
+            recipe: HashMap::from([("flour".to_string(), 1), ("sugar".to_string(), i64::MAX)]),
+            pantry: HashMap::from([("flour".to_string(), 1_000_000_000_000), ("sugar".to_string(), i64::MAX)]),
+        };
+        let result = data.bake();
+        assert_eq!(result.cookies, 1);
+        assert_eq!(result.pantry["flour"], 999_999_999_999);
+        assert_eq!(result.pantry["sugar"], 0);
+    }
+
+    #[test]
+    fn test_bake_with_units() {
+        let data: UnitBakeData = serde_json::from_value(json!({
+            "recipe": { "flour": "250g", "milk": { "amount": 0.5, "unit": "cup" }, "eggs": 2 },
+            "pantry": { "flour": "1.5kg", "milk": { "amount": 500, "unit": "g" }, "eggs": 12 },
+            "densities": { "milk": 1.03 }
+        })).unwrap();
+        assert!(!data.is_plain());
+        let result = data.bake().unwrap();
+        assert_eq!(result.cookies, 4);
+        assert_eq!(result.pantry["flour"], Amount::Text("0.5kg".to_string()));
+        assert_eq!(result.pantry["milk"], Amount::Detailed { amount: 12.628, unit: "g".to_string() });
+        assert_eq!(result.pantry["eggs"], Amount::Plain(4));
+    }
+
+    #[test]
+    fn test_bake_with_units_missing_density() {
+        let data: UnitBakeData = serde_json::from_value(json!({
+            "recipe": { "milk": "1 cup" },
+            "pantry": { "milk": "1kg" }
+        })).unwrap();
+        assert!(data.bake().is_err());
+    }
+
+    #[test]
+    fn test_bake_empty_recipe() {
+        let mut data = BakeData {
+            recipe: HashMap::from([("flour".to_string(), 0)]),
+            pantry: HashMap::from([("flour".to_string(), 10)]),
+        };
+        assert_eq!(data.bake().cookies, 0);
+    }
+
+    type TestRecipe<'a> = (&'a str, u64, Vec<(&'a str, i64)>);
+
+    fn plan_request(recipes: Vec<TestRecipe>, pantry: Vec<(&str, i64)>, time_limit_ms: u64) -> PlanRequest {
+        let ingredients = |list: Vec<(&str, i64)>| list.into_iter().map(|(name, amount)| (name.to_string(), amount)).collect();
+        PlanRequest {
+            recipes: recipes.into_iter()
+                .map(|(name, value, list)| (name.to_string(), PlanRecipe { value, ingredients: ingredients(list) }))
+                .collect(),
+            pantry: ingredients(pantry),
+            time_limit_ms,
+        }
+    }
+
+    #[test]
+    fn test_plan_beats_greedy() {
+        // Greedy prefers the cookie with the best value per gram, which leaves flour unused.
+        let request = plan_request(vec![
+            ("cookie", 5, vec![("flour", 3)]),
+            ("cake", 6, vec![("flour", 4)]),
+        ], vec![("flour", 8)], 1000);
+        let result = super::plan(request);
+        assert_eq!(result.cookies["cake"], 2);
+        assert_eq!(result.cookies["cookie"], 0);
+        assert_eq!(result.value, 12);
+        assert_eq!(result.pantry["flour"], 0);
+        assert_eq!(result.bottleneck, Some("flour".to_string()));
+        assert!(result.optimal);
+    }
+
+    #[test]
+    fn test_plan_time_limit() {
+        let request = plan_request(vec![
+            ("cookie", 3, vec![("flour", 2), ("sugar", 1)]),
+            ("cake", 5, vec![("flour", 3), ("butter", 1)]),
+            ("pie", 4, vec![("butter", 2), ("sugar", 3)]),
+        ], vec![("flour", 1_000_000_000), ("sugar", 1_000_000_000), ("butter", 1_000_000_000)], 0);
+        let result = super::plan(request);
+        assert!(!result.optimal);
+        assert!(result.value > 0);
+        assert!(result.pantry.values().all(|amount| *amount >= 0));
+    }
+
+    #[test]
+    fn test_plan_huge_time_limit() {
+        let request = plan_request(vec![("cookie", 5, vec![("flour", 3)])], vec![("flour", 8)], u64::MAX);
+        let result = super::plan(request);
+        assert_eq!(result.cookies["cookie"], 2);
+        assert!(result.optimal);
+    }
+
+    #[test]
+    fn test_plan_huge_amounts() {
+        let request = plan_request(vec![
+            ("cookie", 1, vec![("flour", i64::MAX), ("sugar", 1)]),
+            ("cake", 2, vec![("flour", i64::MAX), ("sugar", i64::MAX)]),
+        ], vec![("flour", i64::MAX), ("sugar", i64::MAX)], 1000);
+        let result = super::plan(request);
+        assert_eq!(result.cookies["cake"], 1);
+        assert_eq!(result.cookies["cookie"], 0);
+        assert_eq!(result.value, 2);
+        assert!(result.optimal);
+    }
+
+    proptest! {
+        #[test]
+        fn test_bake_matches_iterative(
+            recipe in hash_map("[a-d]", 1i64..20, 1..4),
+            pantry in hash_map("[a-e]", -5i64..200, 0..5),
+        ) {
+            let (cookies, expected_pantry) = bake_iteratively(&recipe, &pantry);
+            let result = BakeData { recipe, pantry }.bake();
+            prop_assert_eq!(result.cookies, cookies);
+            prop_assert_eq!(result.pantry, expected_pantry);
+        }
+    }
+}
@@ -1,56 +1,218 @@
-use axum::extract::Path;
+use std::env;
+use std::sync::Arc;
+use axum::extract::{Path, Query, State};
 use axum::http::StatusCode;
-use axum::routing::{get};
+use axum::Json;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use futures::{stream, StreamExt};
 use serde::{Deserialize, Serialize};
 use tracing::info;
+use crate::pokemon_client::{self, Pokemon, PokemonClient, PokemonError};
+
+#[derive(Clone)]
+struct Day08State {
+    client: Arc<dyn PokemonClient>,
+    concurrency: usize,
+}
 
 pub fn router() -> axum::Router {
+    info!("Initializing Pokémon client.");
+    let shared_state = Day08State {
+        client: pokemon_client::from_env(),
+        concurrency: env::var("POKEAPI_CONCURRENCY").ok().and_then(|value| value.parse().ok()).filter(|value| *value > 0).unwrap_or(4),
+    };
+
     axum::Router::new()
-        .route("/weight/:id", get(day08_get))
-        .route("/drop/:id", get(day08_get_drop))
+        .route("/weight/:name_or_id", get(day08_get))
+        .route("/drop/:name_or_id", get(day08_get_drop))
+        .route("/weight", post(day08_post_weights))
+        .route("/drop", post(day08_post_drops))
+        .with_state(shared_state)
+}
+
+const GRAVITY: f64 = 9.825;
+const DROP_HEIGHT: f64 = 10.0;
+const AIR_DENSITY: f64 = 1.225;
+const DRAG_COEFFICIENT: f64 = 1.0;
+const MAX_BATCH: usize = 100;
+
+async fn day08_get(State(state): State<Day08State>, Path(id): Path<String>) -> Result<String, StatusCode> {
+    day08_get_impl(state.client.as_ref(), &id).await
+}
+
+async fn day08_get_impl(client: &dyn PokemonClient, id: &str) -> Result<String, StatusCode> {
+    let pokemon = client.get_pokemon(id).await?;
+    Ok(format!("{}", weight(&pokemon)))
+}
+
+async fn day08_get_drop(State(state): State<Day08State>, Path(id): Path<String>, Query(params): Query<DropParams>) -> Result<Response, StatusCode> {
+    day08_get_drop_impl(state.client.as_ref(), &id, params).await
+}
+
+async fn day08_get_drop_impl(client: &dyn PokemonClient, id: &str, params: DropParams) -> Result<Response, StatusCode> {
+    let physics = params.physics()?;
+    let pokemon = client.get_pokemon(id).await?;
+    let drop = physics.drop(&pokemon);
+    info!("Drop of {}: {:?}", pokemon.name, drop);
+    if params.format.as_deref() == Some("json") {
+        Ok(Json(drop).into_response())
+    } else {
+        Ok(format!("{}", drop.momentum as f32).into_response())
+    }
+}
+
+fn weight(pokemon: &Pokemon) -> f32 {
+    pokemon.weight as f32 / 10f32
+}
+
+fn momentum(pokemon: &Pokemon) -> f32 {
+    Physics::default().drop(pokemon).momentum as f32
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct DropParams {
+    height: Option<f64>,
+    gravity: Option<String>,
+    drag: Option<bool>,
+    drag_coefficient: Option<f64>,
+    air_density: Option<f64>,
+    format: Option<String>,
+}
+
+impl DropParams {
+    fn physics(&self) -> Result<Physics, StatusCode> {
+        let gravity = match self.gravity.as_deref().map(str::to_lowercase).as_deref() {
+            None => GRAVITY,
+            Some("earth") => 9.80665,
+            Some("moon") => 1.62,
+            Some("mars") => 3.721,
+            Some("jupiter") => 24.79,
+            Some(value) => value.parse::<f64>().map_err(|_| StatusCode::BAD_REQUEST)?,
+        };
+        let physics = Physics {
+            height: self.height.unwrap_or(DROP_HEIGHT),
+            gravity,
+            drag: self.drag.unwrap_or(false),
+            drag_coefficient: self.drag_coefficient.unwrap_or(DRAG_COEFFICIENT),
+            air_density: self.air_density.unwrap_or(AIR_DENSITY),
+        };
+        let valid = [physics.height, physics.gravity, physics.drag_coefficient, physics.air_density].iter()
+            .all(|value| value.is_finite() && *value >= 0.0);
+        if !valid || physics.height == 0.0 || physics.gravity == 0.0 {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        Ok(physics)
+    }
 }
 
-const GRAVITY: f32 = 9.825;
+#[derive(Debug)]
+struct Physics {
+    height: f64,
+    gravity: f64,
+    drag: bool,
+    drag_coefficient: f64,
+    air_density: f64,
+}
 
-#[derive(Deserialize, Serialize, Debug)]
-struct Pokemon {
-    name: String,
-    id: i32,
-    weight: i32,
+impl Default for Physics {
+    fn default() -> Physics {
+        Physics { height: DROP_HEIGHT, gravity: GRAVITY, drag: false, drag_coefficient: DRAG_COEFFICIENT, air_density: AIR_DENSITY }
+    }
 }
-async fn day08_get(Path(id): Path<i32>) -> Result<String, StatusCode> {
-    day08_get_impl("https://pokeapi.co/".to_string(), id).await
+
+#[derive(Serialize, Debug)]
+struct Drop {
+    velocity: f64,
+    momentum: f64,
+    time_to_impact: f64,
 }
 
-async fn get_pokemon(api: String, id: i32) -> Result<Pokemon, StatusCode> {
-    let uri = format!("{}/api/v2/pokemon/{}", api, id);
-    info!("Calling {}", uri);
-    let response = reqwest::get(uri).await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .json::<Pokemon>().await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR);
-    match response {
-        Ok(pokemon) => Ok(pokemon),
-        Err(_) => Err(StatusCode::NOT_FOUND)
+impl Physics {
+    /// Drops the Pokémon from rest. With drag, the air resistance is quadratic in the velocity and
+    /// acts on a circular cross-section as wide as the Pokémon is tall.
+    fn drop(&self, pokemon: &Pokemon) -> Drop {
+        let mass = pokemon.weight as f64 / 10.0;
+        let diameter = pokemon.height as f64 / 10.0;
+        let area = std::f64::consts::PI * (diameter / 2.0).powi(2);
+        let drag = self.drag_coefficient * self.air_density * area;
+        let (velocity, time_to_impact) = if self.drag && drag > 0.0 && mass > 0.0 {
+            let terminal = (2.0 * mass * self.gravity / drag).sqrt();
+            let ratio = self.gravity * self.height / terminal.powi(2);
+            let velocity = terminal * (1.0 - (-2.0 * ratio).exp()).sqrt();
+            // acosh(e^x) = x + ln(1 + sqrt(1 - e^-2x)), which does not overflow for large x.
+            let time = terminal / self.gravity * (ratio + (1.0 + (1.0 - (-2.0 * ratio).exp()).sqrt()).ln());
+            (velocity, time)
+        } else {
+            ((2.0 * self.gravity * self.height).sqrt(), (2.0 * self.height / self.gravity).sqrt())
+        };
+        Drop { velocity, momentum: mass * velocity, time_to_impact }
     }
 }
 
-async fn day08_get_impl(api: String, id: i32) -> Result<String, StatusCode> {
-    let pokemon = get_pokemon(api, id).await?;
-    Ok(format!("{}", pokemon.weight as f32 / 10f32))
+/// Ids may be given as JSON numbers or as strings holding an id or a name.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum PokemonId {
+    Id(i64),
+    Name(String),
+}
+
+impl PokemonId {
+    fn as_string(&self) -> String {
+        match self {
+            PokemonId::Id(id) => id.to_string(),
+            PokemonId::Name(name) => name.clone(),
+        }
+    }
 }
 
-async fn day08_get_drop(Path(id): Path<i32>) -> Result<String, StatusCode> {
-    day08_get_drop_impl("https://pokeapi.co/".to_string(), id).await
+#[derive(Serialize, Debug, PartialEq)]
+struct BatchItem {
+    id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    status: u16,
 }
 
-async fn day08_get_drop_impl(api: String, id: i32) -> Result<String, StatusCode> {
-    let pokemon = get_pokemon(api, id).await?;
-    let speed = (2f32 * GRAVITY * 10f32).sqrt();
-    info!("Speed: {}", speed);
-    let impulse = speed * (pokemon.weight as f32 / 10f32);
-    info!("Impulse: {}", impulse);
-    Ok(format!("{}", impulse))
+async fn day08_post_weights(State(state): State<Day08State>, Json(ids): Json<Vec<PokemonId>>) -> Result<Json<Vec<BatchItem>>, (StatusCode, String)> {
+    batch(state.client.as_ref(), state.concurrency, ids, weight).await.map(Json)
+}
+
+async fn day08_post_drops(State(state): State<Day08State>, Json(ids): Json<Vec<PokemonId>>) -> Result<Json<Vec<BatchItem>>, (StatusCode, String)> {
+    batch(state.client.as_ref(), state.concurrency, ids, momentum).await.map(Json)
+}
+
+/// Looks up all ids with at most `concurrency` requests in flight, keeping the order of the ids.
+/// Batches of more than `MAX_BATCH` ids are rejected.
+async fn batch(client: &dyn PokemonClient, concurrency: usize, ids: Vec<PokemonId>, value: fn(&Pokemon) -> f32) -> Result<Vec<BatchItem>, (StatusCode, String)> {
+    info!("Batch lookup of {} Pokémon", ids.len());
+    if ids.len() > MAX_BATCH {
+        return Err((StatusCode::BAD_REQUEST, format!("at most {} ids can be looked up at once", MAX_BATCH)));
+    }
+    let items = stream::iter(ids.iter().map(PokemonId::as_string))
+        .map(|id| async move {
+            let result = client.get_pokemon(&id).await;
+            match result {
+                Ok(pokemon) => BatchItem { id, name: Some(pokemon.name.clone()), value: Some(value(&pokemon)), error: None, status: StatusCode::OK.as_u16() },
+                Err(error) => {
+                    let message = match &error {
+                        PokemonError::InvalidId => "invalid id".to_string(),
+                        PokemonError::NotFound => "not found".to_string(),
+                        PokemonError::Upstream(message) => message.clone(),
+                    };
+                    BatchItem { id, name: None, value: None, error: Some(message), status: StatusCode::from(error).as_u16() }
+                }
+            }
+        })
+        .buffered(concurrency)
+        .collect()
+        .await;
+    Ok(items)
 }
 
 #[cfg(test)]
@@ -58,7 +220,11 @@ mod tests {
     use wiremock::{Mock, MockServer, ResponseTemplate};
     use wiremock::matchers::{method, path};
 
-    use crate::day_08::Pokemon;
+    use std::time::Duration;
+    use crate::pokemon_client::{FixturePokemonClient, HttpPokemonClient, Pokemon};
+    use axum::body::to_bytes;
+    use axum::http::StatusCode;
+    use super::{DropParams, PokemonId};
 
     #[tokio::test]
     async fn test_day08_get() {
@@ -69,10 +235,11 @@ mod tests {
                 name: "bulbasaur".to_string(),
                 id: 1,
                 weight: 69,
+                height: 7,
             }))
             .mount(&mock_server)
             .await;
-        assert_eq!(super::day08_get_impl(mock_server.uri(), 1).await, Ok("6.9".to_string()));
+        assert_eq!(super::day08_get_impl(&HttpPokemonClient::new(&mock_server.uri(), Duration::from_secs(1), 0), "1").await, Ok("6.9".to_string()));
     }
 
     #[tokio::test]
@@ -84,9 +251,51 @@ mod tests {
                 name: "bulbasaur".to_string(),
                 id: 1,
                 weight: 69,
+                height: 7,
             }))
             .mount(&mock_server)
             .await;
-        assert_eq!(super::day08_get_drop_impl(mock_server.uri(), 1).await,  Ok("96.72314".to_string()));
+        let response = super::day08_get_drop_impl(&HttpPokemonClient::new(&mock_server.uri(), Duration::from_secs(1), 0), "1", DropParams::default()).await.unwrap();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(std::str::from_utf8(&body).unwrap(), "96.72314");
+    }
+
+    #[tokio::test]
+    async fn test_day08_batch() {
+        let client = FixturePokemonClient::from_file("fixtures/pokemon.json").unwrap();
+        let ids = vec![PokemonId::Name("Pikachu".to_string()), PokemonId::Id(1), PokemonId::Name("missingno".to_string()), PokemonId::Name("../1".to_string())];
+        let items = super::batch(&client, 2, ids, super::weight).await.unwrap();
+
+        assert_eq!(items.iter().map(|item| item.status).collect::<Vec<_>>(), vec![200, 200, 404, 400]);
+        assert_eq!(items[0].value, Some(6.0));
+        assert_eq!(items[1].name, Some("bulbasaur".to_string()));
+        assert_eq!(items[2].error, Some("not found".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_day08_batch_too_large() {
+        let client = FixturePokemonClient::from_file("fixtures/pokemon.json").unwrap();
+        let ids = (0..=super::MAX_BATCH as i64).map(PokemonId::Id).collect();
+        let result = super::batch(&client, 2, ids, super::weight).await;
+        assert_eq!(result, Err((StatusCode::BAD_REQUEST, "at most 100 ids can be looked up at once".to_string())));
+    }
+
+    #[test]
+    fn test_day08_drop_physics() {
+        let snorlax = Pokemon { name: "snorlax".to_string(), id: 143, weight: 4600, height: 21 };
+        let moon = DropParams { gravity: Some("Moon".to_string()), ..Default::default() }.physics().unwrap().drop(&snorlax);
+        assert!((moon.velocity - (2.0f64 * 1.62 * 10.0).sqrt()).abs() < 1e-9);
+        assert!((moon.time_to_impact - (20.0f64 / 1.62).sqrt()).abs() < 1e-9);
+        assert!((moon.momentum - 460.0 * moon.velocity).abs() < 1e-9);
+
+        let vacuum = DropParams::default().physics().unwrap().drop(&snorlax);
+        let air = DropParams { drag: Some(true), ..Default::default() }.physics().unwrap().drop(&snorlax);
+        assert!(air.velocity < vacuum.velocity);
+        assert!(air.time_to_impact > vacuum.time_to_impact);
+        let high = DropParams { drag: Some(true), height: Some(1e6), ..Default::default() }.physics().unwrap().drop(&snorlax);
+        assert!(high.time_to_impact.is_finite());
+
+        assert!(DropParams { gravity: Some("pluto".to_string()), ..Default::default() }.physics().is_err());
+        assert!(DropParams { height: Some(-1.0), ..Default::default() }.physics().is_err());
     }
 }
@@ -1,36 +1,336 @@
-use axum::http::StatusCode;
-use axum::routing::post;
+use std::env;
+use std::io::Cursor;
+use std::path::{Component, Path as FilePath, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use axum::body::{Body, Bytes};
+use axum::extract::{DefaultBodyLimit, Path, Query, Request, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::Json;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post, put};
 use axum_extra::extract::Multipart;
-use image::GenericImageView;
+use image::{DynamicImage, ImageError, ImageFormat};
+use image::io::Reader;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use tower_http::services::ServeDir;
-use tracing::info;
+use tracing::{info, warn};
+use crate::asset_store::{AssetInfo, AssetStore};
+use crate::image_cache::ImageCache;
+use crate::colour_analysis::{analyse, count_matching, AlphaPolicy, Analysis, AnalysisOptions, Matcher};
+use crate::image_transform::{Transform, TransformError};
+
+const MAX_PIXELS_VARIABLE: &str = "IMAGE_MAX_PIXELS";
+const ALPHA_POLICY_VARIABLE: &str = "IMAGE_ALPHA_POLICY";
+const MAX_DIMENSION_VARIABLE: &str = "IMAGE_MAX_DIMENSION";
+const CACHE_DIR_VARIABLE: &str = "IMAGE_CACHE_DIR";
+const CACHE_MAX_BYTES_VARIABLE: &str = "IMAGE_CACHE_MAX_BYTES";
+const ASSET_MAX_BYTES_VARIABLE: &str = "ASSET_MAX_BYTES";
+/// Comma-separated bearer tokens for the asset management endpoints, which are disabled without.
+const ADMIN_TOKENS_VARIABLE: &str = "ASSET_ADMIN_TOKENS";
+const CACHE_CONTROL: &str = "public, max-age=86400";
+
+#[derive(Clone)]
+struct Day11State {
+    max_pixels: u64,
+    alpha: AlphaPolicy,
+    assets: AssetStore,
+    admin_tokens: Arc<Vec<String>>,
+    cache: ImageCache,
+    max_dimension: u32,
+}
 
 pub fn router() -> axum::Router {
+    let number = |name: &str, default: u64| env::var(name).ok().and_then(|value| value.parse().ok()).unwrap_or(default);
+    let alpha = env::var(ALPHA_POLICY_VARIABLE).ok()
+        .and_then(|value| serde_json::from_value(serde_json::Value::String(value.to_lowercase())).ok())
+        .unwrap_or_default();
+    let cache_dir = env::var(CACHE_DIR_VARIABLE).map(PathBuf::from).unwrap_or(env::temp_dir().join("cch23-image-cache"));
+    let admin_tokens = env::var(ADMIN_TOKENS_VARIABLE).unwrap_or_default()
+        .split(',')
+        .map(|token| token.trim().to_string())
+        .filter(|token| !token.is_empty())
+        .collect();
+    let assets = AssetStore::new(PathBuf::from("assets"), number(ASSET_MAX_BYTES_VARIABLE, 5 * 1024 * 1024) as usize);
+    let admin = axum::Router::new()
+        .route("/admin/assets", get(day11_list_assets).post(day11_upload_assets))
+        .route("/admin/assets/*path", put(day11_replace_asset).delete(day11_delete_asset))
+        .layer(DefaultBodyLimit::max(assets.max_bytes() + 64 * 1024));
+    let shared_state = Day11State {
+        max_pixels: number(MAX_PIXELS_VARIABLE, 25_000_000),
+        alpha,
+        assets,
+        admin_tokens: Arc::new(admin_tokens),
+        cache: ImageCache::new(cache_dir, number(CACHE_MAX_BYTES_VARIABLE, 256 * 1024 * 1024)),
+        max_dimension: number(MAX_DIMENSION_VARIABLE, 4096) as u32,
+    };
     axum::Router::new()
-        .nest_service("/assets/", ServeDir::new("assets"))
+        .merge(admin)
+        .route("/assets/*path", get(day11_asset))
         .route("/red_pixels", post(day11_post))
+        .route("/analysis", post(day11_analysis))
+        .with_state(shared_state)
 }
 
-async fn day11_post(mut multipart: Multipart) -> Result<String, StatusCode> {
-    let mut red_pixels = 0;
-    while let Some(field) = multipart.next_field().await.unwrap() {
-        let name = field.name().unwrap().to_string();
-        let data = field.bytes().await.unwrap();
+type ImageResult<T> = Result<T, (StatusCode, String)>;
+
+struct Upload {
+    filename: Option<String>,
+    data: Bytes,
+}
+
+#[derive(Deserialize, Debug)]
+struct AlphaParams {
+    alpha: Option<AlphaPolicy>,
+}
+
+#[derive(Serialize, Debug)]
+struct RedPixels {
+    filename: Option<String>,
+    red_pixels: u64,
+}
+
+#[derive(Serialize, Debug)]
+struct NamedAnalysis {
+    filename: Option<String>,
+    #[serde(flatten)]
+    analysis: Analysis,
+}
+
+/// Counts the red pixels of every `image` field. A single image gets the plain count, several
+/// images get a list with one count per image.
+async fn day11_post(State(state): State<Day11State>, Query(params): Query<AlphaParams>, multipart: Multipart) -> ImageResult<Response> {
+    let (uploads, _) = read_uploads(multipart).await?;
+    let alpha = params.alpha.unwrap_or(state.alpha);
+    let mut results = Vec::new();
+    for upload in uploads {
+        let image = decode_image(upload.data, state.max_pixels).await?;
+        let red_pixels = tokio::task::spawn_blocking(move || count_matching(&image, &Matcher::RedDominant, alpha)).await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        info!("Red pixels in {:?}: {}", upload.filename, red_pixels);
+        results.push(RedPixels { filename: upload.filename, red_pixels });
+    }
+    Ok(match results.as_slice() {
+        [result] => format!("{}", result.red_pixels).into_response(),
+        _ => Json(results).into_response(),
+    })
+}
+
+/// Expects `image` fields and optionally an `options` field with the analysis options as JSON.
+async fn day11_analysis(State(state): State<Day11State>, multipart: Multipart) -> ImageResult<Response> {
+    let (uploads, options) = read_uploads(multipart).await?;
+    let mut options: AnalysisOptions = match options {
+        Some(options) => serde_json::from_slice(&options).map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid options: {}", e)))?,
+        None => AnalysisOptions::default(),
+    };
+    options.alpha = options.alpha.or(Some(state.alpha));
+    let mut results = Vec::new();
+    for upload in uploads {
+        let image = decode_image(upload.data, state.max_pixels).await?;
+        info!("Analysing {}x{} image {:?} with {:?}", image.width(), image.height(), upload.filename, options);
+        let task_options = options.clone();
+        let analysis = tokio::task::spawn_blocking(move || analyse(&image, &task_options)).await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+            .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+        results.push(NamedAnalysis { filename: upload.filename, analysis });
+    }
+    Ok(if results.len() == 1 {
+        Json(results.remove(0).analysis).into_response()
+    } else {
+        Json(results).into_response()
+    })
+}
+
+async fn read_uploads(mut multipart: Multipart) -> ImageResult<(Vec<Upload>, Option<Bytes>)> {
+    let bad_request = |message: String| (StatusCode::BAD_REQUEST, message);
+    let mut uploads = Vec::new();
+    let mut options = None;
+    while let Some(field) = multipart.next_field().await.map_err(|e| bad_request(format!("invalid multipart body: {}", e)))? {
+        let name = field.name().ok_or(bad_request("multipart field without a name".to_string()))?.to_string();
+        let filename = field.file_name().map(str::to_string);
+        let data = field.bytes().await.map_err(|e| bad_request(format!("could not read field `{}`: {}", name, e)))?;
         info!("Length of `{}` is {} bytes", name, data.len());
-        if name == "image" {
-            let image = image::load_from_memory(&data).unwrap();
-            info!("Image size: {}x{}", image.width(), image.height());
-            image.pixels().for_each(|pixel| {
-                let pixel_data = pixel.2;
-                let red = pixel_data[0] as i32;
-                let green = pixel_data[1] as i32;
-                let blue = pixel_data[2] as i32;
-                if red > (blue + green) {
-                    red_pixels += 1;
-                }
-            });
-            info!("Red pixels: {}", red_pixels);
+        match name.as_str() {
+            "image" => uploads.push(Upload { filename, data }),
+            "options" => options = Some(data),
+            _ => warn!("Ignoring field `{}`", name),
+        }
+    }
+    if uploads.is_empty() {
+        return Err(bad_request("missing `image` field".to_string()));
+    }
+    Ok((uploads, options))
+}
+
+/// Decodes an image on the blocking pool, checking the dimensions before decoding the pixels.
+async fn decode_image(data: Bytes, max_pixels: u64) -> ImageResult<DynamicImage> {
+    tokio::task::spawn_blocking(move || {
+        let reader = Reader::new(Cursor::new(&data)).with_guessed_format()
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("could not read image: {}", e)))?;
+        let format = reader.format().ok_or((StatusCode::UNSUPPORTED_MEDIA_TYPE, "unrecognized image format".to_string()))?;
+        let (width, height) = Reader::with_format(Cursor::new(&data), format).into_dimensions().map_err(image_error)?;
+        if width as u64 * height as u64 > max_pixels {
+            return Err((StatusCode::PAYLOAD_TOO_LARGE, format!("image has {}x{} pixels, at most {} are allowed", width, height, max_pixels)));
+        }
+        reader.decode().map_err(image_error)
+    }).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+}
+
+/// Serves the asset as is, or transformed if any transformation is requested. Transformed images
+/// are cached on disk under a key made of the asset, its size and modification time, and the
+/// transformation, which doubles as the ETag. Cache hits do not read the asset, the output format
+/// follows from the `format` parameter or the extension of the asset.
+async fn day11_asset(State(state): State<Day11State>, Path(path): Path<String>, Query(transform): Query<Transform>, request: Request) -> ImageResult<Response> {
+    if transform.is_empty() {
+        return serve_asset(state.assets.dir(), request).await;
+    }
+    transform.validate(state.max_dimension).map_err(transform_error)?;
+    let source = asset_path(state.assets.dir(), &path)?;
+    let metadata = tokio::fs::metadata(&source).await.map_err(|_| (StatusCode::NOT_FOUND, format!("asset {} not found", path)))?;
+    let modified = metadata.modified().ok().and_then(|time| time.duration_since(UNIX_EPOCH).ok()).unwrap_or_default();
+    let key = format!("{:x}", Sha256::digest(format!("{}|{}|{}|{}", path, metadata.len(), modified.as_nanos(), transform.key())));
+    let etag = format!("\"{}\"", key);
+    if request.headers().get(header::IF_NONE_MATCH).is_some_and(|value| value.as_bytes() == etag.as_bytes()) {
+        return Ok((StatusCode::NOT_MODIFIED, asset_headers(&etag, None)).into_response());
+    }
+
+    let format = transform.output_format(ImageFormat::from_path(&source).ok());
+    let cached = format!("{}.{}", key, format.extension());
+    if let Some(body) = state.cache.get(&cached).await {
+        info!("Serving {} from the image cache", path);
+        return Ok((asset_headers(&etag, Some(format.content_type())), body).into_response());
+    }
+    let data = Bytes::from(tokio::fs::read(&source).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?);
+    let image = decode_image(data, state.max_pixels).await?;
+    let task_transform = transform.clone();
+    let (max_dimension, max_pixels) = (state.max_dimension, state.max_pixels);
+    let body = tokio::task::spawn_blocking(move || task_transform.apply(image, max_dimension, max_pixels).and_then(|image| task_transform.encode(&image, format)))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(transform_error)?;
+    info!("Transformed {} with {:?} to {} bytes", path, transform, body.len());
+    if let Err(e) = state.cache.put(&cached, &body).await {
+        warn!("Could not cache {}: {}", cached, e);
+    }
+    Ok((asset_headers(&etag, Some(format.content_type())), body).into_response())
+}
+
+async fn serve_asset(assets_dir: &FilePath, mut request: Request) -> ImageResult<Response> {
+    let uri = request.uri().path().strip_prefix("/assets").unwrap_or("/").to_string();
+    *request.uri_mut() = uri.parse().map_err(|_| (StatusCode::BAD_REQUEST, "invalid asset path".to_string()))?;
+    let response = ServeDir::new(assets_dir).try_call(request).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(response.map(Body::new))
+}
+
+/// Resolves a path below the assets directory, rejecting anything that could leave it.
+fn asset_path(assets_dir: &FilePath, path: &str) -> ImageResult<PathBuf> {
+    let relative = FilePath::new(path);
+    if path.is_empty() || !relative.components().all(|component| matches!(component, Component::Normal(_))) {
+        return Err((StatusCode::BAD_REQUEST, format!("invalid asset path {}", path)));
+    }
+    Ok(assets_dir.join(relative))
+}
+
+fn asset_headers(etag: &str, content_type: Option<&'static str>) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    if let Ok(etag) = HeaderValue::from_str(etag) {
+        headers.insert(header::ETAG, etag);
+    }
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static(CACHE_CONTROL));
+    if let Some(content_type) = content_type {
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    }
+    headers
+}
+
+fn authorize(state: &Day11State, headers: &HeaderMap) -> ImageResult<()> {
+    if state.admin_tokens.is_empty() {
+        return Err((StatusCode::FORBIDDEN, "asset management is disabled".to_string()));
+    }
+    let token = headers.get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or((StatusCode::UNAUTHORIZED, "missing bearer token".to_string()))?;
+    // Comparing digests keeps the comparison time independent of how much of the token matches.
+    let digest = Sha256::digest(token.trim());
+    if !state.admin_tokens.iter().any(|known| Sha256::digest(known) == digest) {
+        return Err((StatusCode::UNAUTHORIZED, "invalid bearer token".to_string()));
+    }
+    Ok(())
+}
+
+async fn day11_list_assets(State(state): State<Day11State>, headers: HeaderMap) -> ImageResult<Json<Vec<AssetInfo>>> {
+    authorize(&state, &headers)?;
+    Ok(Json(state.assets.list().await?))
+}
+
+/// Stores every `file` field under its file name, refusing to overwrite existing assets.
+async fn day11_upload_assets(State(state): State<Day11State>, headers: HeaderMap, mut multipart: Multipart) -> ImageResult<(StatusCode, Json<Vec<AssetInfo>>)> {
+    authorize(&state, &headers)?;
+    let bad_request = |message: String| (StatusCode::BAD_REQUEST, message);
+    let mut stored = Vec::new();
+    while let Some(field) = multipart.next_field().await.map_err(|e| bad_request(format!("invalid multipart body: {}", e)))? {
+        if field.name() != Some("file") {
+            warn!("Ignoring field {:?}", field.name());
+            continue;
         }
+        let filename = field.file_name().ok_or(bad_request("file field without a file name".to_string()))?.to_string();
+        let data = field.bytes().await.map_err(|e| bad_request(format!("could not read {}: {}", filename, e)))?;
+        stored.push(state.assets.put(&filename, &data, false).await?);
     }
-    Ok(format!("{}", red_pixels))
-}
\ No newline at end of file
+    if stored.is_empty() {
+        return Err(bad_request("missing `file` field".to_string()));
+    }
+    Ok((StatusCode::CREATED, Json(stored)))
+}
+
+async fn day11_replace_asset(State(state): State<Day11State>, headers: HeaderMap, Path(path): Path<String>, body: Bytes) -> ImageResult<Json<AssetInfo>> {
+    authorize(&state, &headers)?;
+    Ok(Json(state.assets.put(&path, &body, true).await?))
+}
+
+async fn day11_delete_asset(State(state): State<Day11State>, headers: HeaderMap, Path(path): Path<String>) -> ImageResult<StatusCode> {
+    authorize(&state, &headers)?;
+    state.assets.delete(&path).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn transform_error(error: TransformError) -> (StatusCode, String) {
+    match error {
+        TransformError::Invalid(message) => (StatusCode::BAD_REQUEST, message),
+        TransformError::Unsupported(message) => (StatusCode::UNSUPPORTED_MEDIA_TYPE, message),
+    }
+}
+
+fn image_error(error: ImageError) -> (StatusCode, String) {
+    match error {
+        ImageError::Unsupported(e) => (StatusCode::UNSUPPORTED_MEDIA_TYPE, format!("unsupported image: {}", e)),
+        ImageError::Limits(e) => (StatusCode::PAYLOAD_TOO_LARGE, format!("image is too large: {}", e)),
+        e => (StatusCode::BAD_REQUEST, format!("invalid image: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use axum::http::StatusCode;
+    use super::{asset_path, decode_image};
+
+    #[tokio::test]
+    async fn test_decode_image() {
+        let png = std::fs::read("assets/decoration.png").unwrap();
+        assert!(decode_image(png.clone().into(), 25_000_000).await.is_ok());
+        assert_eq!(decode_image(png.clone().into(), 1000).await.unwrap_err().0, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(decode_image(png[..200].to_vec().into(), 25_000_000).await.unwrap_err().0, StatusCode::BAD_REQUEST);
+        assert_eq!(decode_image(b"not an image".to_vec().into(), 25_000_000).await.unwrap_err().0, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[test]
+    fn test_asset_path() {
+        assert_eq!(asset_path(Path::new("assets"), "icons/star.png").unwrap(), Path::new("assets/icons/star.png"));
+        assert!(asset_path(Path::new("assets"), "../Cargo.toml").is_err());
+        assert!(asset_path(Path::new("assets"), "/etc/passwd").is_err());
+        assert!(asset_path(Path::new("assets"), "icons/./star.png").is_ok());
+    }
+}
@@ -1,66 +1,319 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::env;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
-use axum::extract::{Path, State};
+use axum::extract::{Path, Query, State};
 use axum::http::StatusCode;
 use axum::Json;
-use axum::routing::{get, post};
-use chrono::{Datelike, DateTime, Utc};
+use axum::routing::{delete, get, post};
+use chrono::{Datelike, DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
-use tracing::info;
-use ulid::Ulid;
+use sqlx::PgPool;
+use tracing::{info, warn};
+use ulid::{Generator, Ulid};
 use uuid::Uuid;
+use crate::clock::{Clock, ClockState, TestClock};
+use crate::ulid_query::{parse_timezone, Predicate};
+use crate::timestamp_store::{Entry, MemoryTimestampStore, PgTimestampStore, TimestampStore};
+
+const TTL_VARIABLE: &str = "PACKET_TTL_SECS";
+const SWEEP_INTERVAL_VARIABLE: &str = "PACKET_SWEEP_INTERVAL_SECS";
+const MAX_GENERATED_ULIDS: usize = 1000;
+const MAX_PREDICATES: usize = 32;
 
 #[derive(Clone)]
 struct Day12State {
-    day12: Arc<Mutex<HashMap<String, DateTime<Utc>>>>,
+    store: Arc<dyn TimestampStore>,
+    default_ttl: Option<Duration>,
+    clock: Arc<dyn Clock>,
+    /// Only available outside production, see `clock::from_env`.
+    clock_control: Option<Arc<TestClock>>,
+    /// Shared so that ULIDs generated for the current time are monotonic across requests.
+    generator: Arc<Mutex<Generator>>,
 }
 
-pub fn router() -> axum::Router {
+pub fn router(pool: Option<PgPool>) -> axum::Router {
     info!("Initializing state.");
+    let store: Arc<dyn TimestampStore> = match pool {
+        Some(pool) => Arc::new(PgTimestampStore::new(pool)),
+        None => Arc::new(MemoryTimestampStore::default()),
+    };
+    let (clock, clock_control) = crate::clock::from_env();
+    let number = |name: &str| env::var(name).ok().and_then(|value| value.parse::<i64>().ok()).filter(|value| *value > 0);
     let shared_state = Day12State {
-        day12: Arc::new(Mutex::new(HashMap::new())),
+        store: store.clone(),
+        default_ttl: number(TTL_VARIABLE).and_then(seconds),
+        clock: clock.clone(),
+        clock_control,
+        generator: Arc::new(Mutex::new(Generator::new())),
     };
-
+    if let Some(interval) = number(SWEEP_INTERVAL_VARIABLE).or(shared_state.default_ttl.map(|_| 60)) {
+        tokio::spawn(sweep(store, clock, std::time::Duration::from_secs(interval as u64)));
+    }
 
     axum::Router::new()
         .route("/save/:text", post(day12_save))
         .route("/load/:text", get(day12_load))
+        .route("/packets", get(day12_packets))
+        .route("/packets/:text", delete(day12_delete))
         .route("/ulids", post(day12_ulids))
+        .route("/ulids/generate", post(day12_generate_ulids))
+        .route("/ulids/decode", post(day12_decode_ulids))
+        .route("/ulids/to-uuid", post(day12_ulids_to_uuids))
+        .route("/ulids/to-ulid", post(day12_uuids_to_ulids))
+        .route("/ulids/query", post(day12_query_ulids))
         .route("/ulids/:weekday", post(day12_ulids_weekday))
+        .route("/admin/clock", get(day12_clock).post(day12_control_clock))
         .with_state(shared_state)
 }
 
+/// Like `Duration::seconds`, but `None` instead of a panic if `value` is out of range.
+fn seconds(value: i64) -> Option<Duration> {
+    let duration = Duration::from_std(std::time::Duration::from_secs(value.unsigned_abs())).ok()?;
+    Some(if value < 0 { -duration } else { duration })
+}
+
+async fn sweep(store: Arc<dyn TimestampStore>, clock: Arc<dyn Clock>, interval: std::time::Duration) {
+    let mut timer = tokio::time::interval(interval);
+    loop {
+        timer.tick().await;
+        match store.sweep(clock.now()).await {
+            Ok(0) => {}
+            Ok(swept) => info!("Swept {} expired packets.", swept),
+            Err(e) => warn!("Could not sweep expired packets: {}", e.0),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct SaveParams {
+    /// Seconds until the packet is forgotten.
+    ttl: Option<i64>,
+}
+
+#[derive(Serialize, Debug)]
+struct Packet {
+    #[serde(flatten)]
+    entry: Entry,
+    age_seconds: i64,
+}
 
-async fn day12_save(State(state): State<Day12State>, Path(text): Path<String>) -> Result<(),StatusCode> {
-    let mut texts = state.day12.lock().map_err(|_| StatusCode::BAD_REQUEST)?;
-    let now = chrono::offset::Utc::now();
-    info!("Got text: {} and store it with time {}", text, now);
-    texts.insert(text.clone(), now);
+async fn day12_save(State(state): State<Day12State>, Path(text): Path<String>, Query(params): Query<SaveParams>) -> Result<(),StatusCode> {
+    let ttl = match params.ttl {
+        Some(ttl) if ttl <= 0 => return Err(StatusCode::BAD_REQUEST),
+        Some(ttl) => Some(seconds(ttl).ok_or(StatusCode::BAD_REQUEST)?),
+        None => state.default_ttl,
+    };
+    let now = state.clock.now();
+    if ttl.is_some_and(|ttl| now.checked_add_signed(ttl).is_none()) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    info!("Got text: {} and store it with time {} and ttl {:?}", text, now, ttl);
+    state.store.save(&text, now, ttl).await?;
     Ok(())
 }
 
 async fn day12_load(State(state): State<Day12State>, Path(text): Path<String>) -> Result<String, StatusCode> {
     info!("Load text: {}", text);
-    match state.day12.lock().map_err(|_| StatusCode::BAD_REQUEST)?.get(&text) {
-        Some(date) => Ok((chrono::offset::Utc::now()-date).num_seconds().to_string()),
+    let now = state.clock.now();
+    match state.store.load(&text, now).await? {
+        Some(entry) => Ok((now - entry.saved_at).num_seconds().to_string()),
         None => Err(StatusCode::NOT_FOUND)
     }
 }
 
-async fn day12_ulids(Json(ulids): Json<Vec<String>>) -> Result<Json<Vec<String>>, StatusCode> {
+async fn day12_packets(State(state): State<Day12State>) -> Result<Json<Vec<Packet>>, StatusCode> {
+    let now = state.clock.now();
+    let packets = state.store.list(now).await?.into_iter()
+        .map(|entry| Packet { age_seconds: (now - entry.saved_at).num_seconds(), entry })
+        .collect();
+    Ok(Json(packets))
+}
+
+async fn day12_delete(State(state): State<Day12State>, Path(text): Path<String>) -> Result<StatusCode, StatusCode> {
+    info!("Delete text: {}", text);
+    if state.store.delete(&text).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(StatusCode::NOT_FOUND)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "action", rename_all = "lowercase")]
+enum ClockCommand {
+    Freeze { at: Option<DateTime<Utc>> },
+    Advance { seconds: i64 },
+    Resume,
+}
+
+async fn day12_clock(State(state): State<Day12State>) -> Result<Json<ClockState>, StatusCode> {
+    let clock = state.clock_control.ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(clock.state()))
+}
+
+async fn day12_control_clock(State(state): State<Day12State>, Json(command): Json<ClockCommand>) -> Result<Json<ClockState>, StatusCode> {
+    let clock = state.clock_control.ok_or(StatusCode::NOT_FOUND)?;
+    info!("Clock command: {:?}", command);
+    Ok(Json(match command {
+        ClockCommand::Freeze { at } => clock.freeze(at),
+        ClockCommand::Advance { seconds: value } => seconds(value).and_then(|duration| clock.advance(duration)).ok_or(StatusCode::BAD_REQUEST)?,
+        ClockCommand::Resume => clock.resume(),
+    }))
+}
+
+/// The result for one entry of a batch, either a value or the reason why the entry was invalid.
+#[derive(Serialize, Debug, Eq, PartialEq)]
+struct UlidItem<T> {
+    input: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl<T> UlidItem<T> {
+    fn new(input: &str, result: Result<T, String>) -> UlidItem<T> {
+        match result {
+            Ok(value) => UlidItem { input: input.to_string(), value: Some(value), error: None },
+            Err(error) => UlidItem { input: input.to_string(), value: None, error: Some(error) },
+        }
+    }
+}
+
+fn parse_ulid(input: &str) -> Result<Ulid, String> {
+    Ulid::from_str(input.trim()).map_err(|e| format!("invalid ULID: {}", e))
+}
+
+fn convert<T>(inputs: &[String], conversion: fn(&str) -> Result<T, String>) -> Vec<UlidItem<T>> {
+    inputs.iter().map(|input| UlidItem::new(input, conversion(input))).collect()
+}
+
+async fn day12_ulids(Json(ulids): Json<Vec<String>>) -> Result<Json<Vec<String>>, (StatusCode, Json<Vec<UlidItem<String>>>)> {
     info!("Got ulids: {:?}", ulids);
-    let mut uuids: Vec<String> = Vec::new();
-    for ulid_str in ulids.iter() {
-        let ulid = Ulid::from_str(&ulid_str).map_err(|_| StatusCode::BAD_REQUEST)?;
-        let uuid = Uuid::from_bytes(ulid.to_bytes());
-        uuids.push(uuid.to_string());
+    let items = convert(&ulids, ulid_to_uuid);
+    if items.iter().any(|item| item.error.is_some()) {
+        return Err((StatusCode::BAD_REQUEST, Json(items)));
     }
+    let mut uuids: Vec<String> = items.into_iter().filter_map(|item| item.value).collect();
     uuids.reverse();
     info!("Converted uuids: {:?}", uuids);
     Ok(Json(uuids))
 }
 
+fn ulid_to_uuid(input: &str) -> Result<String, String> {
+    Ok(Uuid::from_bytes(parse_ulid(input)?.to_bytes()).to_string())
+}
+
+fn uuid_to_ulid(input: &str) -> Result<String, String> {
+    let uuid = Uuid::parse_str(input.trim()).map_err(|e| format!("invalid UUID: {}", e))?;
+    Ok(Ulid::from_bytes(uuid.into_bytes()).to_string())
+}
+
+async fn day12_ulids_to_uuids(Json(ulids): Json<Vec<String>>) -> Json<Vec<UlidItem<String>>> {
+    Json(convert(&ulids, ulid_to_uuid))
+}
+
+async fn day12_uuids_to_ulids(Json(uuids): Json<Vec<String>>) -> Json<Vec<UlidItem<String>>> {
+    Json(convert(&uuids, uuid_to_ulid))
+}
+
+#[derive(Serialize, Debug, Eq, PartialEq)]
+struct DecodedUlid {
+    uuid: String,
+    timestamp_ms: u64,
+    datetime: DateTime<Utc>,
+    /// The 80 random bits as hexadecimal.
+    randomness: String,
+}
+
+fn decode_ulid(input: &str) -> Result<DecodedUlid, String> {
+    let ulid = parse_ulid(input)?;
+    Ok(DecodedUlid {
+        uuid: Uuid::from_bytes(ulid.to_bytes()).to_string(),
+        timestamp_ms: ulid.timestamp_ms(),
+        datetime: ulid.datetime().into(),
+        randomness: format!("{:020x}", ulid.random()),
+    })
+}
+
+async fn day12_decode_ulids(Json(ulids): Json<Vec<String>>) -> Json<Vec<UlidItem<DecodedUlid>>> {
+    Json(convert(&ulids, decode_ulid))
+}
+
+#[derive(Deserialize, Debug)]
+struct GenerateRequest {
+    #[serde(default = "default_count")]
+    count: usize,
+    /// Generates the ULIDs for this time instead of now.
+    timestamp: Option<DateTime<Utc>>,
+}
+
+fn default_count() -> usize {
+    1
+}
+
+/// Generates ULIDs that increase monotonically, even within the same millisecond.
+async fn day12_generate_ulids(State(state): State<Day12State>, Json(request): Json<GenerateRequest>) -> Result<Json<Vec<String>>, (StatusCode, String)> {
+    if !(1..=MAX_GENERATED_ULIDS).contains(&request.count) {
+        return Err((StatusCode::BAD_REQUEST, format!("count must be between 1 and {}", MAX_GENERATED_ULIDS)));
+    }
+    if request.timestamp.is_some_and(|timestamp| timestamp.timestamp_millis() < 0 || timestamp.timestamp_millis() >= 1 << 48) {
+        return Err((StatusCode::BAD_REQUEST, "timestamp is out of the range of ULIDs".to_string()));
+    }
+    let mut own_generator = Generator::new();
+    let mut shared_generator = state.generator.lock().map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let (generator, datetime) = match request.timestamp {
+        Some(timestamp) => (&mut own_generator, timestamp),
+        None => (&mut *shared_generator, state.clock.now()),
+    };
+    let ulids = (0..request.count)
+        .map(|_| generator.generate_from_datetime(datetime.into()).map(|ulid| ulid.to_string()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| (StatusCode::SERVICE_UNAVAILABLE, e.to_string()))?;
+    info!("Generated {} ulids for {}", ulids.len(), datetime);
+    Ok(Json(ulids))
+}
+
+#[derive(Deserialize, Debug)]
+struct QueryRequest {
+    ulids: Vec<String>,
+    /// Predicate expressions by name, see `ulid_query::Predicate`.
+    predicates: BTreeMap<String, String>,
+    #[serde(default)]
+    timezone: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct QueryResult {
+    counts: BTreeMap<String, usize>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    invalid: Vec<UlidItem<String>>,
+}
+
+async fn day12_query_ulids(State(state): State<Day12State>, Json(request): Json<QueryRequest>) -> Result<Json<QueryResult>, (StatusCode, String)> {
+    let timezone = parse_timezone(request.timezone.as_deref().unwrap_or("UTC")).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    if request.predicates.len() > MAX_PREDICATES {
+        return Err((StatusCode::BAD_REQUEST, format!("at most {} predicates are allowed", MAX_PREDICATES)));
+    }
+    let predicates = request.predicates.iter()
+        .map(|(name, source)| Predicate::parse(source).map(|predicate| (name, predicate)))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid predicate: {}", e)))?;
+    let (ulids, invalid): (Vec<_>, Vec<_>) = request.ulids.iter()
+        .map(|input| UlidItem::new(input, parse_ulid(input)))
+        .partition(|item| item.error.is_none());
+    let now = state.clock.now();
+    let counts = predicates.iter()
+        .map(|(name, predicate)| {
+            let count = ulids.iter().filter_map(|item| item.value.as_ref()).filter(|ulid| predicate.matches(ulid, &timezone, now)).count();
+            (name.to_string(), count)
+        })
+        .collect();
+    let invalid = invalid.into_iter().map(|item| UlidItem { input: item.input, value: None, error: item.error }).collect();
+    Ok(Json(QueryResult { counts, invalid }))
+}
+
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
 struct UlidCriteria {
     #[serde(rename = "christmas eve")]
@@ -72,7 +325,7 @@ struct UlidCriteria {
     lsb_is_1: i32,
 }
 
-async fn day12_ulids_weekday(Path(weekday): Path<u32>, Json(ulids): Json<Vec<String>>) -> Result<Json<UlidCriteria>, StatusCode> {
+async fn day12_ulids_weekday(State(state): State<Day12State>, Path(weekday): Path<u32>, Json(ulids): Json<Vec<String>>) -> Result<Json<UlidCriteria>, StatusCode> {
     info!("Got ulids: {:?} and weekday {}", ulids, weekday);
     let mut criterias = UlidCriteria {
             christmas_eve: 0,
@@ -83,7 +336,7 @@ async fn day12_ulids_weekday(Path(weekday): Path<u32>, Json(ulids): Json<Vec<Str
     for ulid_str in ulids.iter() {
         let ulid = Ulid::from_str(&ulid_str).map_err(|_| StatusCode::BAD_REQUEST)?;
         let datetime: DateTime<Utc> = ulid.datetime().into();
-        let now = Utc::now();
+        let now = state.clock.now();
         if datetime.weekday().num_days_from_monday() == weekday {
             criterias.weekday += 1;
         }
@@ -102,7 +355,52 @@ async fn day12_ulids_weekday(Path(weekday): Path<u32>, Json(ulids): Json<Vec<Str
 
 #[cfg(test)]
 mod tests {
-    use axum::extract::Path;
+    use std::sync::Arc;
+    use axum::extract::{Path, State};
+    use chrono::{Duration, TimeZone, Utc};
+    use crate::clock::TestClock;
+    use crate::timestamp_store::MemoryTimestampStore;
+    use super::Day12State;
+
+    fn state() -> (Day12State, Arc<TestClock>) {
+        let clock = Arc::new(TestClock::frozen_at(Utc.with_ymd_and_hms(2023, 12, 12, 12, 0, 0).unwrap()));
+        let state = Day12State {
+            store: Arc::new(MemoryTimestampStore::default()),
+            default_ttl: None,
+            clock: clock.clone(),
+            clock_control: Some(clock.clone()),
+            generator: Arc::new(std::sync::Mutex::new(ulid::Generator::new())),
+        };
+        (state, clock)
+    }
+
+    #[tokio::test]
+    async fn test_day12_save_load() {
+        let (state, clock) = state();
+        super::day12_save(State(state.clone()), Path("packet".to_string()), axum::extract::Query(super::SaveParams { ttl: Some(5) })).await.unwrap();
+        clock.advance(Duration::seconds(2));
+        assert_eq!(super::day12_load(State(state.clone()), Path("packet".to_string())).await, Ok("2".to_string()));
+        clock.advance(Duration::seconds(3));
+        assert_eq!(super::day12_load(State(state), Path("packet".to_string())).await, Err(axum::http::StatusCode::NOT_FOUND));
+    }
+
+    #[tokio::test]
+    async fn test_day12_advance_clock_out_of_range() {
+        let (state, _) = state();
+        for seconds in [i64::MAX, i64::MIN, 10_000_000_000_000] {
+            let result = super::day12_control_clock(State(state.clone()), axum::Json(super::ClockCommand::Advance { seconds })).await;
+            assert_eq!(result.err(), Some(axum::http::StatusCode::BAD_REQUEST), "{}", seconds);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_day12_save_huge_ttl() {
+        let (state, _) = state();
+        for ttl in [i64::MAX, 9_000_000_000_000_000, 10_000_000_000_000] {
+            let result = super::day12_save(State(state.clone()), Path("packet".to_string()), axum::extract::Query(super::SaveParams { ttl: Some(ttl) })).await;
+            assert_eq!(result, Err(axum::http::StatusCode::BAD_REQUEST), "{}", ttl);
+        }
+    }
 
     #[tokio::test]
     async fn test_day12_ulids() {
@@ -115,6 +413,8 @@ mod tests {
         ])).await;
         assert!(uuid_result.is_ok());
         let uuids = uuid_result.unwrap().0;
+        let ulids = super::day12_uuids_to_ulids(axum::Json(uuids.clone())).await.0;
+        assert_eq!(ulids[4].value.as_deref(), Some("01BJQ0E1C3Z56ABCD0E11HYX4M"));
         assert_eq!(uuids, vec![
             "015cae07-0583-f94c-a5b1-a070431f7516",
             "015cae07-0583-f94c-a5b1-a070431f74f8",
@@ -126,7 +426,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_day12_ulids_weekday() {
-        let criteria_result = super::day12_ulids_weekday(Path(5), axum::Json(vec![
+        let criteria_result = super::day12_ulids_weekday(State(state().0), Path(5), axum::Json(vec![
             "00WEGGF0G0J5HEYXS3D7RWZGV8".to_string(),
             "76EP4G39R8JD1N8AQNYDVJBRCF".to_string(),
             "018CJ7KMG0051CDCS3B7BFJ3AK".to_string(),
@@ -147,4 +447,54 @@ mod tests {
         });
     }
 
-}
\ No newline at end of file
+    #[tokio::test]
+    async fn test_day12_ulid_items() {
+        let error = super::day12_ulids(axum::Json(vec!["01BJQ0E1C3Z56ABCD0E11HYX4M".to_string(), "not a ulid".to_string()])).await.unwrap_err();
+        assert_eq!(error.0, axum::http::StatusCode::BAD_REQUEST);
+        assert!(error.1.0[0].error.is_none());
+        assert!(error.1.0[1].error.as_deref().unwrap().starts_with("invalid ULID"));
+
+        let decoded = super::day12_decode_ulids(axum::Json(vec!["01BJQ0E1C3Z56ABCD0E11HYX4M".to_string()])).await.0;
+        let decoded = decoded[0].value.as_ref().unwrap();
+        assert_eq!(decoded.timestamp_ms, 1_497_568_314_755);
+        assert_eq!(decoded.uuid, "015cae07-0583-f94c-a5b1-a070431f7494");
+        assert_eq!(decoded.randomness, "f94ca5b1a070431f7494");
+    }
+
+    #[tokio::test]
+    async fn test_day12_generate_ulids() {
+        let (state, clock) = state();
+        let request = super::GenerateRequest { count: 3, timestamp: None };
+        let ulids = super::day12_generate_ulids(State(state.clone()), axum::Json(request)).await.unwrap().0;
+        assert!(ulids.windows(2).all(|pair| pair[0] < pair[1]));
+        let first: ulid::Ulid = ulids[0].parse().unwrap();
+        assert_eq!(first.timestamp_ms() as i64, clock.state().now.timestamp_millis());
+
+        let at = Utc.with_ymd_and_hms(2023, 12, 24, 0, 0, 0).unwrap();
+        let ulids = super::day12_generate_ulids(State(state.clone()), axum::Json(super::GenerateRequest { count: 2, timestamp: Some(at) })).await.unwrap().0;
+        assert_eq!(ulids[1].parse::<ulid::Ulid>().unwrap().timestamp_ms() as i64, at.timestamp_millis());
+        assert!(super::day12_generate_ulids(State(state), axum::Json(super::GenerateRequest { count: 0, timestamp: None })).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_day12_query_ulids() {
+        let request = super::QueryRequest {
+            ulids: vec!["00WEGGF0G0J5HEYXS3D7RWZGV8".to_string(), "018CJ7KMG0051CDCS3B7BFJ3AK".to_string(), "76EP4G39R8JD1N8AQNYDVJBRCF".to_string(), "invalid".to_string()],
+            predicates: [("christmas eve", "month = 12 and day = 24"), ("in the future", "future"), ("LSB is 1", "bit 0 = 1")]
+                .into_iter().map(|(name, source)| (name.to_string(), source.to_string())).collect(),
+            timezone: None,
+        };
+        let result = super::day12_query_ulids(State(state().0), axum::Json(request)).await.unwrap().0;
+        assert_eq!(result.counts["christmas eve"], 2);
+        assert_eq!(result.counts["in the future"], 1);
+        assert_eq!(result.counts["LSB is 1"], 2);
+        assert_eq!(result.invalid.len(), 1);
+
+        let request = super::QueryRequest { ulids: vec![], predicates: [("broken".to_string(), "day ==".to_string())].into(), timezone: None };
+        assert!(super::day12_query_ulids(State(state().0), axum::Json(request)).await.is_err());
+
+        let predicates = (0..=super::MAX_PREDICATES).map(|index| (index.to_string(), "future".to_string())).collect();
+        let request = super::QueryRequest { ulids: vec![], predicates, timezone: None };
+        assert_eq!(super::day12_query_ulids(State(state().0), axum::Json(request)).await.unwrap_err().0, axum::http::StatusCode::BAD_REQUEST);
+    }
+}
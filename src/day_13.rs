@@ -1,22 +1,26 @@
-use std::collections::HashMap;
+use std::sync::Arc;
 use axum::extract::State;
 use axum::http::StatusCode;
 use axum::Json;
+use axum::response::IntoResponse;
 use axum::routing::{get, post};
 use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, PgPool, Row};
-use sqlx::postgres::PgRow;
+use sqlx::{FromRow, PgPool};
 use tracing::info;
+use crate::aggregate_cache::{AggregateCache, CacheMetrics};
+use crate::database;
 
 #[derive(Clone)]
 struct Day13State {
-    db_pool: Option<PgPool>
+    db_pool: Option<PgPool>,
+    cache: Arc<AggregateCache>,
 }
 
-pub fn router(pool: Option<PgPool>) -> axum::Router {
+pub fn router(pool: Option<PgPool>, cache: Arc<AggregateCache>) -> axum::Router {
     info!("Initializing state.");
     let shared_state = Day13State {
         db_pool: pool,
+        cache,
     };
 
     axum::Router::new()
@@ -25,6 +29,7 @@ pub fn router(pool: Option<PgPool>) -> axum::Router {
         .route("/orders", post(day13_insert_orders))
         .route("/orders/total", get(day13_total_orders))
         .route("/orders/popular", get(day13_popular_orders))
+        .route("/cache/metrics", get(day13_cache_metrics))
         .with_state(shared_state)
 }
 
@@ -44,47 +49,54 @@ struct Order {
 
 async fn day13_sql(State(state): State<Day13State>) -> Result<String, StatusCode> {
     info!("Get SQL called.");
-    let pool = state.db_pool.unwrap();
-    match sqlx::query_as::<_, Get>("SELECT * FROM day13_get")
-        .fetch_one(&pool)
+    let pool = database::pool(&state.db_pool)?;
+    let get = sqlx::query_as::<_, Get>("SELECT * FROM day13_get")
+        .fetch_optional(pool)
         .await
-    {
-        Ok(get) => Ok(format!("{}", get.num)),
-        Err(_) => Err(StatusCode::BAD_REQUEST),
-    }
+        .map_err(|e| database::read_error("Get SQL", e))?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    Ok(format!("{}", get.num))
 }
 
 async fn day13_reset(State(state): State<Day13State>) -> Result<StatusCode, StatusCode> {
     info!("Reset SQL called.");
-    let pool = state.db_pool.unwrap();
+    let result = reset_orders(database::pool(&state.db_pool)?).await;
+    state.cache.invalidate();
+    result
+}
+
+async fn reset_orders(pool: &PgPool) -> Result<StatusCode, StatusCode> {
+    let write_error = |e| database::write_error("Reset orders", e);
     sqlx::query("DROP TABLE IF EXISTS orders")
-        .execute(&pool)
-        .await.map_err(|_| StatusCode::BAD_REQUEST)?;
-    match sqlx::query("CREATE TABLE orders (
+        .execute(pool)
+        .await.map_err(write_error)?;
+    sqlx::query("CREATE TABLE orders (
             id INT PRIMARY KEY,
             region_id INT,
             gift_name VARCHAR(50),
             quantity INT
         )")
-        .execute(&pool)
-        .await
-    {
-        Ok(_) => Ok(StatusCode::OK),
-        Err(_) => Err(StatusCode::BAD_REQUEST),
-    }
+        .execute(pool)
+        .await.map_err(write_error)?;
+    Ok(StatusCode::OK)
 }
 
 async fn day13_insert_orders(State(state): State<Day13State>, Json(orders): Json<Vec<Order>>) -> Result<StatusCode,StatusCode> {
     info!("Insert orders: {:?}", orders);
-    let pool = state.db_pool.unwrap();
+    let result = insert_orders(database::pool(&state.db_pool)?, orders).await;
+    state.cache.invalidate();
+    result
+}
+
+async fn insert_orders(pool: &PgPool, orders: Vec<Order>) -> Result<StatusCode,StatusCode> {
     for order in orders {
         let _ = sqlx::query("INSERT INTO orders (id, region_id, gift_name, quantity) VALUES ($1, $2, $3, $4)")
             .bind(order.id)
             .bind(order.region_id)
             .bind(&order.gift_name)
             .bind(order.quantity)
-            .execute(&pool)
-            .await.map_err(|_| StatusCode::BAD_REQUEST)?;
+            .execute(pool)
+            .await.map_err(|e| database::write_error("Insert orders", e))?;
 
     }
     Ok(StatusCode::OK)
@@ -95,16 +107,20 @@ struct OrderCount {
     pub total: i64,
 }
 
-async fn day13_total_orders(State(state): State<Day13State>) -> Result<Json<OrderCount>, StatusCode> {
+async fn day13_total_orders(State(state): State<Day13State>) -> Result<impl IntoResponse, StatusCode> {
     info!("Total orders called.");
-    let pool = state.db_pool.unwrap();
-    let row: PgRow = sqlx::query("SELECT SUM(quantity) FROM orders")
-        .fetch_one(&pool)
+    let pool = database::pool(&state.db_pool)?;
+    let (total, status) = state.cache.get_or_compute("day13/orders/total", total_orders(pool)).await?;
+    Ok((status.header(), Json(total)))
+}
+
+async fn total_orders(pool: &PgPool) -> Result<OrderCount, StatusCode> {
+    let total: i64 = sqlx::query_scalar("SELECT COALESCE(SUM(quantity), 0)::INT8 FROM orders")
+        .fetch_one(pool)
         .await
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
-    let total: i64 = row.try_get(0).map_err(|_| StatusCode::BAD_REQUEST)?;
+        .map_err(|e| database::read_error("Total orders", e))?;
     info!("Total orders: {}", total);
-    Ok(Json(OrderCount { total }))
+    Ok(OrderCount { total })
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -112,30 +128,23 @@ struct Popular {
     pub popular: Option<String>,
 }
 
-async fn day13_popular_orders(State(state): State<Day13State>) -> Result<Json<Popular>,StatusCode> {
+async fn day13_popular_orders(State(state): State<Day13State>) -> Result<impl IntoResponse, StatusCode> {
     info!("Popular orders called.");
-    let pool = state.db_pool.unwrap();
-    let rows = sqlx::query("SELECT * FROM orders")
-        .fetch_all(&pool)
-        .await.unwrap_or_else(|_| vec![]);
-    if rows.is_empty() {
-        return Ok(Json(Popular { popular: None }))
-    }
-    let mut orders: HashMap<String,i32> = HashMap::new();
-    rows.iter()
-        .for_each(|row| {
-            let name = row.get::<String, _>("gift_name");
-            let quantity = row.get::<i32, _>("quantity");
-            if orders.contains_key(&name) {
-                orders.insert(name.clone(), orders.get(&name).unwrap() + quantity);
-            } else {
-                orders.insert(name.clone(), quantity);
-            }
-        });
-    let (popular, quantity) = orders.iter()
-        .max_by_key(|order| order.1)
-        .ok_or(StatusCode::BAD_REQUEST)?;
-    info!("Popular order: {} with {} orders", popular, quantity);
-    Ok(Json(Popular { popular: Some(popular.to_string()) }))
+    let pool = database::pool(&state.db_pool)?;
+    let (popular, status) = state.cache.get_or_compute("day13/orders/popular", popular_orders(pool)).await?;
+    Ok((status.header(), Json(popular)))
 }
 
+async fn popular_orders(pool: &PgPool) -> Result<Popular, StatusCode> {
+    let popular: Option<String> = sqlx::query_scalar("SELECT gift_name FROM orders WHERE gift_name IS NOT NULL
+            GROUP BY gift_name ORDER BY COALESCE(SUM(quantity), 0) DESC, gift_name LIMIT 1")
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| database::read_error("Popular orders", e))?;
+    info!("Popular order: {:?}", popular);
+    Ok(Popular { popular })
+}
+
+async fn day13_cache_metrics(State(state): State<Day13State>) -> Json<CacheMetrics> {
+    Json(state.cache.metrics())
+}
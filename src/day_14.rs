@@ -1,6 +1,9 @@
+use std::sync::Arc;
 use axum::extract::State;
+use axum::http::{header, HeaderValue, StatusCode};
 use axum::Json;
-use axum::response::IntoResponse;
+use axum::middleware;
+use axum::response::{IntoResponse, Response};
 use axum::routing::{post};
 use axum_template::engine::Engine;
 use axum_template::RenderHtml;
@@ -8,10 +11,15 @@ use handlebars::Handlebars;
 use serde::{Deserialize, Serialize};
 use tracing::info;
 use crate::{AppEngine};
+use crate::html_sanitizer::HtmlPolicy;
+
+/// Rendered content may contain markup, so nothing is allowed to load, run or embed the page.
+const CONTENT_SECURITY_POLICY: &str = "default-src 'none'; base-uri 'none'; form-action 'none'; frame-ancestors 'none'";
 
 #[derive(Clone)]
 struct Day14State {
-    template_engine: AppEngine
+    template_engine: AppEngine,
+    policy: Arc<HtmlPolicy>,
 }
 
 pub fn router() -> axum::Router {
@@ -22,15 +30,29 @@ pub fn router() -> axum::Router {
 
     info!("Initializing state.");
     let shared_state = Day14State {
-        template_engine: Engine::from(hbs)
+        template_engine: Engine::from(hbs),
+        policy: Arc::new(HtmlPolicy::from_env()),
     };
 
     axum::Router::new()
         .route("/unsafe", post(day14_unsafe))
         .route("/safe", post(day14_safe))
+        .route("/sanitized", post(day14_sanitized))
+        .fallback(not_found)
+        .layer(middleware::map_response(content_security_policy))
         .with_state(shared_state)
 }
 
+/// Added before the layer, so that unknown paths below `/14` get the policy as well.
+async fn not_found() -> StatusCode {
+    StatusCode::NOT_FOUND
+}
+
+async fn content_security_policy(mut response: Response) -> Response {
+    response.headers_mut().insert(header::CONTENT_SECURITY_POLICY, HeaderValue::from_static(CONTENT_SECURITY_POLICY));
+    response
+}
+
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 struct HtmlContent {
@@ -47,4 +69,11 @@ async fn day14_safe(State(state): State<Day14State>, Json(html_content): Json<Ht
     info!("Get safe called with content {:?}.", html_content);
     let trimmed = HtmlContent { content: html_content.content.trim().to_string() };
     RenderHtml("safe", state.template_engine, trimmed)
-}
\ No newline at end of file
+}
+
+/// Keeps the markup allowed by the policy, which the unsafe template then renders as is.
+async fn day14_sanitized(State(state): State<Day14State>, Json(html_content): Json<HtmlContent>) -> impl IntoResponse {
+    info!("Get sanitized called with content {:?}.", html_content);
+    let sanitized = HtmlContent { content: state.policy.clean(html_content.content.trim()) };
+    RenderHtml("unsafe", state.template_engine, sanitized)
+}
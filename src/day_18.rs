@@ -1,21 +1,30 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 use axum::extract::{Path, State};
 use axum::http::StatusCode;
 use axum::Json;
+use axum::response::IntoResponse;
 use axum::routing::{get, post};
 use serde::{Deserialize, Serialize};
 use sqlx::{FromRow, PgPool, Row};
 use tracing::info;
+use crate::aggregate_cache::{AggregateCache, CacheMetrics};
+use crate::database;
+
+/// Largest `top_list` length, each length is cached separately.
+const MAX_TOP_GIFTS: i32 = 100;
 
 #[derive(Clone)]
 struct Day18State {
-    db_pool: Option<PgPool>
+    db_pool: Option<PgPool>,
+    cache: Arc<AggregateCache>,
 }
 
-pub fn router(pool: Option<PgPool>) -> axum::Router {
+pub fn router(pool: Option<PgPool>, cache: Arc<AggregateCache>) -> axum::Router {
     info!("Initializing state.");
     let shared_state = Day18State {
         db_pool: pool,
+        cache,
     };
 
     axum::Router::new()
@@ -24,6 +33,7 @@ pub fn router(pool: Option<PgPool>) -> axum::Router {
         .route("/regions", post(day18_insert_regions))
         .route("/regions/total", get(day18_total_orders_per_region))
         .route("/regions/top_list/:num", get(day18_popular_orders_per_region))
+        .route("/cache/metrics", get(day18_cache_metrics))
         .with_state(shared_state)
 }
 
@@ -43,44 +53,52 @@ struct Order {
 
 async fn day18_reset(State(state): State<Day18State>) -> Result<StatusCode, StatusCode> {
     info!("Reset SQL called.");
-    let pool = state.db_pool.unwrap();
+    let result = reset_tables(database::pool(&state.db_pool)?).await;
+    state.cache.invalidate();
+    result
+}
+
+async fn reset_tables(pool: &PgPool) -> Result<StatusCode, StatusCode> {
+    let write_error = |e| database::write_error("Reset tables", e);
     sqlx::query("DROP TABLE IF EXISTS orders")
-        .execute(&pool)
-        .await.map_err(|_| StatusCode::BAD_REQUEST)?;
+        .execute(pool)
+        .await.map_err(write_error)?;
     sqlx::query("DROP TABLE IF EXISTS regions")
-        .execute(&pool)
-        .await.map_err(|_| StatusCode::BAD_REQUEST)?;
+        .execute(pool)
+        .await.map_err(write_error)?;
     sqlx::query("CREATE TABLE orders (
             id INT PRIMARY KEY,
             region_id INT,
             gift_name VARCHAR(50),
             quantity INT
         )")
-        .execute(&pool)
-        .await.map_err(|_| StatusCode::BAD_REQUEST)?;
-    match sqlx::query("CREATE TABLE regions (
+        .execute(pool)
+        .await.map_err(write_error)?;
+    sqlx::query("CREATE TABLE regions (
             id INT PRIMARY KEY,
             name VARCHAR(50)
         )")
-        .execute(&pool)
-        .await
-    {
-        Ok(_) => Ok(StatusCode::OK),
-        Err(_) => Err(StatusCode::BAD_REQUEST),
-    }
+        .execute(pool)
+        .await.map_err(write_error)?;
+    Ok(StatusCode::OK)
 }
 
 async fn day18_insert_orders(State(state): State<Day18State>, Json(orders): Json<Vec<Order>>) -> Result<StatusCode,StatusCode> {
     info!("Insert orders: {:?}", orders);
-    let pool = state.db_pool.unwrap();
+    let result = insert_orders(database::pool(&state.db_pool)?, orders).await;
+    state.cache.invalidate();
+    result
+}
+
+async fn insert_orders(pool: &PgPool, orders: Vec<Order>) -> Result<StatusCode,StatusCode> {
     for order in orders {
         let _ = sqlx::query("INSERT INTO orders (id, region_id, gift_name, quantity) VALUES ($1, $2, $3, $4)")
             .bind(order.id)
             .bind(order.region_id)
             .bind(&order.gift_name)
             .bind(order.quantity)
-            .execute(&pool)
-            .await.map_err(|_| StatusCode::BAD_REQUEST)?;
+            .execute(pool)
+            .await.map_err(|e| database::write_error("Insert orders", e))?;
 
     }
     Ok(StatusCode::OK)
@@ -94,13 +112,18 @@ struct Region {
 
 async fn day18_insert_regions(State(state): State<Day18State>, Json(regions): Json<Vec<Region>>) -> Result<StatusCode,StatusCode> {
     info!("Insert regions: {:?}", regions);
-    let pool = state.db_pool.unwrap();
+    let result = insert_regions(database::pool(&state.db_pool)?, regions).await;
+    state.cache.invalidate();
+    result
+}
+
+async fn insert_regions(pool: &PgPool, regions: Vec<Region>) -> Result<StatusCode,StatusCode> {
     for region in regions {
         let _ = sqlx::query("INSERT INTO regions (id, name) VALUES ($1, $2)")
             .bind(region.id)
             .bind(&region.name)
-            .execute(&pool)
-            .await.map_err(|_| StatusCode::BAD_REQUEST)?;
+            .execute(pool)
+            .await.map_err(|e| database::write_error("Insert regions", e))?;
 
     }
     Ok(StatusCode::OK)
@@ -113,23 +136,28 @@ struct OrderPerRegionCount {
     pub total: i64,
 }
 
-async fn day18_total_orders_per_region(State(state): State<Day18State>) -> Result<Json<Vec<OrderPerRegionCount>>, StatusCode> {
+async fn day18_total_orders_per_region(State(state): State<Day18State>) -> Result<impl IntoResponse, StatusCode> {
     info!("Total orders per region called.");
-    let pool = state.db_pool.unwrap();
-    let rows = sqlx::query("SELECT regions.name, SUM(orders.quantity) FROM orders INNER JOIN regions ON orders.region_id = regions.id GROUP BY regions.name")
-        .fetch_all(&pool)
+    let pool = database::pool(&state.db_pool)?;
+    let (totals, status) = state.cache.get_or_compute("day18/regions/total", total_orders_per_region(pool)).await?;
+    Ok((status.header(), Json(totals)))
+}
+
+async fn total_orders_per_region(pool: &PgPool) -> Result<Vec<OrderPerRegionCount>, StatusCode> {
+    let rows = sqlx::query("SELECT regions.name, COALESCE(SUM(orders.quantity), 0)::INT8 FROM orders INNER JOIN regions ON orders.region_id = regions.id GROUP BY regions.name")
+        .fetch_all(pool)
         .await
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
+        .map_err(|e| database::read_error("Total orders per region", e))?;
     let totals: Result<Vec<OrderPerRegionCount>, StatusCode> = rows.iter().map(|row| {
-        let region: String = row.try_get(0).map_err(|_| StatusCode::BAD_REQUEST)?;
-        let total: i64 = row.try_get(1).map_err(|_| StatusCode::BAD_REQUEST)?;
+        let region: String = row.try_get(0).map_err(|e| database::read_error("Total orders per region", e))?;
+        let total: i64 = row.try_get(1).map_err(|e| database::read_error("Total orders per region", e))?;
         info!("Total orders for region {}: {}", region, total);
         Ok(OrderPerRegionCount { region, total })
     }).collect();
     match totals {
         Ok(mut totals) => {
             totals.sort();
-            Ok(Json(totals))
+            Ok(totals)
         },
         Err(e) => Err(e),
     }
@@ -141,32 +169,35 @@ struct Popular {
     pub top_gifts: Vec<String>,
 }
 
-async fn day18_popular_orders_per_region(State(state): State<Day18State>, Path(max): Path<i32>) -> Result<Json<Vec<Popular>>,StatusCode> {
+async fn day18_popular_orders_per_region(State(state): State<Day18State>, Path(max): Path<i32>) -> Result<impl IntoResponse, StatusCode> {
     info!("Popular orders per region called.");
-    let pool = state.db_pool.unwrap();
-    let popular_orders_per_region = sqlx::query("SELECT regions.name, orders.gift_name, SUM(orders.quantity) FROM orders INNER JOIN regions ON orders.region_id = regions.id GROUP BY regions.name, orders.gift_name")
-        .fetch_all(&pool)
-        .await.unwrap_or_else(|_| vec![]);
+    if !(0..=MAX_TOP_GIFTS).contains(&max) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let pool = database::pool(&state.db_pool)?;
+    let key = format!("day18/regions/top_list/{}", max);
+    let (popular, status) = state.cache.get_or_compute(&key, popular_orders_per_region(pool, max)).await?;
+    Ok((status.header(), Json(popular)))
+}
+
+async fn popular_orders_per_region(pool: &PgPool, max: i32) -> Result<Vec<Popular>, StatusCode> {
+    let read_error = |e| database::read_error("Popular orders per region", e);
+    let popular_orders_per_region = sqlx::query("SELECT regions.name, orders.gift_name, COALESCE(SUM(orders.quantity), 0)::INT8 FROM orders INNER JOIN regions ON orders.region_id = regions.id WHERE orders.gift_name IS NOT NULL GROUP BY regions.name, orders.gift_name")
+        .fetch_all(pool)
+        .await.map_err(read_error)?;
     info!("Popular orders per region received");
     let mut orders: HashMap<String,Vec<(String, i64)>> = HashMap::new();
-    popular_orders_per_region.iter()
-        .for_each(|row| {
-            let region = row.get::<String, _>("name");
-            let gift_name = row.get::<String, _>("gift_name");
-            let quantity = row.get::<i64, _>(2);
-            if orders.contains_key(&region) {
-                let region_orders = orders.get_mut(&region).unwrap();
-                region_orders.push((gift_name.clone(), quantity));
-            } else {
-                let region_orders = vec![(gift_name.clone(), quantity)];
-                orders.insert(region.clone(), region_orders);
-            }
-        });
+    for row in popular_orders_per_region {
+        let region = row.try_get::<String, _>("name").map_err(read_error)?;
+        let gift_name = row.try_get::<String, _>("gift_name").map_err(read_error)?;
+        let quantity = row.try_get::<i64, _>(2).map_err(read_error)?;
+        orders.entry(region).or_default().push((gift_name, quantity));
+    }
     let rows = sqlx::query("SELECT name FROM regions")
-        .fetch_all(&pool)
-        .await.unwrap_or_else(|_| vec![]);
+        .fetch_all(pool)
+        .await.map_err(read_error)?;
     for row in rows {
-        let region = row.get::<String, _>("name");
+        let region = row.try_get::<String, _>("name").map_err(read_error)?;
         if !orders.contains_key(&region) {
             orders.insert(region.clone(), vec![]);
         }
@@ -189,5 +220,9 @@ async fn day18_popular_orders_per_region(State(state): State<Day18State>, Path(m
         })
         .collect();
     popular.sort_by(|a, b| a.region.cmp(&b.region));
-    Ok(Json(popular))
+    Ok(popular)
+}
+
+async fn day18_cache_metrics(State(state): State<Day18State>) -> Json<CacheMetrics> {
+    Json(state.cache.metrics())
 }
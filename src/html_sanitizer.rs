@@ -0,0 +1,123 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::env;
+use tracing::{info, warn};
+
+const TAGS_VARIABLE: &str = "HTML_ALLOWED_TAGS";
+const ATTRIBUTES_VARIABLE: &str = "HTML_ALLOWED_ATTRIBUTES";
+const URL_SCHEMES_VARIABLE: &str = "HTML_URL_SCHEMES";
+
+const DEFAULT_TAGS: &str = "a,abbr,b,blockquote,br,code,em,h1,h2,h3,h4,h5,h6,hr,i,li,ol,p,pre,s,strong,sub,sup,u,ul";
+const DEFAULT_ATTRIBUTES: &str = "a:href,a:title,abbr:title";
+const DEFAULT_URL_SCHEMES: &str = "http,https,mailto";
+
+/// Never allowed, whatever is configured. The content of `script` and `style` is removed with the
+/// tags, `svg` and `math` bring their own scripting and parsing rules, forms, frames and `base`,
+/// `meta` and `link` can send, load or redirect elsewhere, event handlers and `style` can run code,
+/// and `rel` is always set to `noopener noreferrer`.
+const FORBIDDEN_TAGS: [&str; 13] = [
+    "script", "style", "iframe", "object", "embed", "svg", "math", "form", "base", "meta", "link", "frame", "frameset",
+];
+const FORBIDDEN_ATTRIBUTES: [&str; 2] = ["style", "rel"];
+const FORBIDDEN_URL_SCHEMES: [&str; 3] = ["javascript", "data", "vbscript"];
+
+/// An allow-list of tags, attributes and URL schemes, everything else is stripped.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HtmlPolicy {
+    tags: BTreeSet<String>,
+    /// Attributes per tag, `*` allows an attribute on all tags.
+    attributes: BTreeMap<String, BTreeSet<String>>,
+    url_schemes: BTreeSet<String>,
+}
+
+impl Default for HtmlPolicy {
+    fn default() -> HtmlPolicy {
+        HtmlPolicy::parse(DEFAULT_TAGS, DEFAULT_ATTRIBUTES, DEFAULT_URL_SCHEMES)
+    }
+}
+
+impl HtmlPolicy {
+    /// Reads comma-separated lists from `HTML_ALLOWED_TAGS`, `HTML_ALLOWED_ATTRIBUTES` (as
+    /// `tag:attribute`) and `HTML_URL_SCHEMES`, each falls back to its default when unset.
+    pub fn from_env() -> HtmlPolicy {
+        let variable = |name: &str, default: &str| env::var(name).unwrap_or(default.to_string());
+        let policy = HtmlPolicy::parse(
+            &variable(TAGS_VARIABLE, DEFAULT_TAGS),
+            &variable(ATTRIBUTES_VARIABLE, DEFAULT_ATTRIBUTES),
+            &variable(URL_SCHEMES_VARIABLE, DEFAULT_URL_SCHEMES),
+        );
+        info!("HTML policy: {:?}", policy);
+        policy
+    }
+
+    fn parse(tags: &str, attributes: &str, url_schemes: &str) -> HtmlPolicy {
+        let list = |value: &str| -> Vec<String> {
+            value.split(',').map(|item| item.trim().to_lowercase()).filter(|item| !item.is_empty()).collect()
+        };
+        let tags = list(tags).into_iter()
+            .filter(|tag| allowed(tag, FORBIDDEN_TAGS.contains(&tag.as_str()), "tag"))
+            .collect();
+        let mut allowed_attributes: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        for attribute in list(attributes) {
+            let Some((tag, name)) = attribute.split_once(':') else {
+                warn!("Ignoring HTML attribute '{}' without tag.", attribute);
+                continue;
+            };
+            let forbidden = name.starts_with("on") || FORBIDDEN_ATTRIBUTES.contains(&name)
+                || FORBIDDEN_TAGS.contains(&tag);
+            if allowed(&attribute, forbidden, "attribute") {
+                allowed_attributes.entry(tag.to_string()).or_default().insert(name.to_string());
+            }
+        }
+        let url_schemes = list(url_schemes).into_iter()
+            .filter(|scheme| allowed(scheme, FORBIDDEN_URL_SCHEMES.contains(&scheme.as_str()), "URL scheme"))
+            .collect();
+        HtmlPolicy { tags, attributes: allowed_attributes, url_schemes }
+    }
+
+    pub fn clean(&self, html: &str) -> String {
+        let mut builder = ammonia::Builder::default();
+        builder
+            .tags(strs(&self.tags))
+            .generic_attributes(self.attributes.get("*").map(strs).unwrap_or_default())
+            .tag_attributes(self.attributes.iter()
+                .filter(|(tag, _)| tag.as_str() != "*")
+                .map(|(tag, names)| (tag.as_str(), strs(names)))
+                .collect())
+            .url_schemes(strs(&self.url_schemes));
+        builder.clean(html).to_string()
+    }
+}
+
+fn strs(set: &BTreeSet<String>) -> HashSet<&str> {
+    set.iter().map(String::as_str).collect()
+}
+
+fn allowed(item: &str, forbidden: bool, kind: &str) -> bool {
+    if forbidden {
+        warn!("Ignoring forbidden HTML {} '{}'.", kind, item);
+    }
+    !forbidden
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HtmlPolicy;
+
+    #[test]
+    fn test_clean() {
+        let policy = HtmlPolicy::default();
+        assert_eq!(policy.clean("<p onclick=\"alert(1)\">Hi <b>Santa</b><script>alert(2)</script></p>"), "<p>Hi <b>Santa</b></p>");
+        assert_eq!(policy.clean("<a href=\"https://shuttle.rs\" target=\"_blank\">Shuttle</a>"), "<a href=\"https://shuttle.rs\" rel=\"noopener noreferrer\">Shuttle</a>");
+        assert_eq!(policy.clean("<a href=\"javascript:alert(1)\">Click</a>"), "<a rel=\"noopener noreferrer\">Click</a>");
+        assert_eq!(policy.clean("<img src=x onerror=alert(1)><iframe src=\"https://evil\"></iframe>"), "");
+    }
+
+    #[test]
+    fn test_forbidden_items_are_ignored() {
+        let policy = HtmlPolicy::parse("p, SCRIPT, img", "img:src,p:onclick,*:title,*:style,a:rel,broken", "https,javascript");
+        assert_eq!(policy, HtmlPolicy::parse("img,p", "img:src,*:title", "https"));
+        assert_eq!(policy.clean("<script>alert(1)</script><p title=\"t\" style=\"x\">x</p><img src=\"data:image/png;base64,AA\">"), "<p title=\"t\">x</p><img>");
+        let policy = HtmlPolicy::parse("p,svg,math,form,base,meta,link,frame,frameset", "form:action,link:href", "https");
+        assert_eq!(policy, HtmlPolicy::parse("p", "", "https"));
+    }
+}
@@ -0,0 +1,102 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+/// Transformed images on disk, limited to `max_bytes` in total. Hits refresh the modification time
+/// of a file, so that the least recently used files are removed first when the cache is full.
+#[derive(Clone, Debug)]
+pub struct ImageCache {
+    dir: PathBuf,
+    max_bytes: u64,
+}
+
+impl ImageCache {
+    pub fn new(dir: PathBuf, max_bytes: u64) -> ImageCache {
+        ImageCache { dir, max_bytes }
+    }
+
+    pub async fn get(&self, name: &str) -> Option<Vec<u8>> {
+        let path = self.dir.join(name);
+        let body = tokio::fs::read(&path).await.ok()?;
+        if let Err(e) = touch(&path).await {
+            warn!("Could not touch {}: {}", path.display(), e);
+        }
+        Some(body)
+    }
+
+    /// Writes to a temporary file first, so that concurrent requests never read a partial image.
+    pub async fn put(&self, name: &str, body: &[u8]) -> std::io::Result<()> {
+        if body.len() as u64 > self.max_bytes {
+            info!("Not caching {} with {} bytes", name, body.len());
+            return Ok(());
+        }
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let path = self.dir.join(name);
+        let temporary = self.dir.join(format!("{}.{}.tmp", name, uuid::Uuid::new_v4()));
+        tokio::fs::write(&temporary, body).await?;
+        tokio::fs::rename(&temporary, &path).await?;
+        self.evict(&path).await
+    }
+
+    /// Removes the least recently used files until the cache fits into `max_bytes`, keeping `keep`.
+    async fn evict(&self, keep: &Path) -> std::io::Result<()> {
+        let mut files = Vec::new();
+        let mut total = 0;
+        let mut entries = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let Ok(metadata) = entry.metadata().await else { continue };
+            if metadata.is_file() {
+                total += metadata.len();
+                files.push((metadata.modified().unwrap_or(UNIX_EPOCH), metadata.len(), entry.path()));
+            }
+        }
+        files.sort();
+        for (_, len, path) in files {
+            if total <= self.max_bytes {
+                break;
+            }
+            if path == keep {
+                continue;
+            }
+            // Another request may have removed the file already.
+            if tokio::fs::remove_file(&path).await.is_ok() {
+                info!("Evicted {} from the image cache", path.display());
+                total -= len;
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn touch(path: &Path) -> std::io::Result<()> {
+    let file = tokio::fs::File::options().write(true).open(path).await?.into_std().await;
+    tokio::task::spawn_blocking(move || file.set_modified(SystemTime::now())).await?
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+    use super::ImageCache;
+
+    fn age(cache: &ImageCache, name: &str, seconds: u64) {
+        let file = std::fs::File::options().write(true).open(cache.dir.join(name)).unwrap();
+        file.set_modified(SystemTime::now() - Duration::from_secs(seconds)).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_evicts_least_recently_used() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ImageCache::new(dir.path().to_path_buf(), 25);
+        cache.put("a.png", &[0; 10]).await.unwrap();
+        cache.put("b.png", &[1; 10]).await.unwrap();
+        age(&cache, "a.png", 20);
+        age(&cache, "b.png", 10);
+        assert_eq!(cache.get("a.png").await, Some(vec![0; 10]));
+        cache.put("c.png", &[2; 10]).await.unwrap();
+        assert_eq!(cache.get("b.png").await, None);
+        assert_eq!(cache.get("a.png").await, Some(vec![0; 10]));
+        assert_eq!(cache.get("c.png").await, Some(vec![2; 10]));
+        cache.put("d.png", &[3; 30]).await.unwrap();
+        assert_eq!(cache.get("d.png").await, None);
+    }
+}
@@ -0,0 +1,206 @@
+use std::io::Cursor;
+use image::{DynamicImage, ImageFormat, ImageOutputFormat};
+use image::imageops::FilterType;
+use serde::Deserialize;
+
+const DEFAULT_QUALITY: u8 = 85;
+
+#[derive(Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Png,
+    #[serde(alias = "jpg")]
+    Jpeg,
+    Webp,
+}
+
+impl OutputFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "image/png",
+            OutputFormat::Jpeg => "image/jpeg",
+            OutputFormat::Webp => "image/webp",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::Webp => "webp",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TransformError {
+    Invalid(String),
+    Unsupported(String),
+}
+
+/// Transformations are applied in a fixed order: crop, rotate, resize and thumbnail.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Transform {
+    /// `x,y,width,height` of the area to keep.
+    pub crop: Option<String>,
+    /// Clockwise rotation in degrees, a multiple of 90.
+    pub rotate: Option<u32>,
+    /// Target width, the height follows the aspect ratio unless it is given as well.
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Fits the image into a square with this edge, keeping the aspect ratio.
+    pub thumbnail: Option<u32>,
+    pub format: Option<OutputFormat>,
+    /// JPEG quality from 1 to 100.
+    pub quality: Option<u8>,
+}
+
+impl Transform {
+    pub fn is_empty(&self) -> bool {
+        *self == Transform::default()
+    }
+
+    /// A canonical description of the transformation, used as part of cache keys.
+    pub fn key(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    /// Checks the parameters without looking at an image, so that oversized requests fail early.
+    pub fn validate(&self, max_dimension: u32) -> Result<(), TransformError> {
+        for (name, value) in [("width", self.width), ("height", self.height), ("thumbnail", self.thumbnail)] {
+            match value {
+                Some(0) => return Err(TransformError::Invalid(format!("{} must be positive", name))),
+                Some(value) if value > max_dimension => {
+                    return Err(TransformError::Invalid(format!("{} must be at most {}", name, max_dimension)));
+                }
+                _ => {}
+            }
+        }
+        if self.rotate.is_some_and(|rotate| rotate % 90 != 0) {
+            return Err(TransformError::Invalid("rotate must be a multiple of 90".to_string()));
+        }
+        if self.quality.is_some_and(|quality| !(1..=100).contains(&quality)) {
+            return Err(TransformError::Invalid("quality must be between 1 and 100".to_string()));
+        }
+        if self.format == Some(OutputFormat::Webp) {
+            return Err(TransformError::Unsupported("WebP output is not available in this build".to_string()));
+        }
+        self.crop_area().map(|_| ())
+    }
+
+    fn crop_area(&self) -> Result<Option<[u32; 4]>, TransformError> {
+        let Some(crop) = &self.crop else { return Ok(None) };
+        let invalid = || TransformError::Invalid(format!("invalid crop '{}', expected x,y,width,height", crop));
+        let values = crop.split(',').map(|value| value.trim().parse::<u32>()).collect::<Result<Vec<_>, _>>().map_err(|_| invalid())?;
+        match values.as_slice() {
+            [x, y, width, height] if *width > 0 && *height > 0 => Ok(Some([*x, *y, *width, *height])),
+            _ => Err(invalid()),
+        }
+    }
+
+    /// The output format, defaulting to the format of the source if it can be written.
+    pub fn output_format(&self, source: Option<ImageFormat>) -> OutputFormat {
+        self.format.unwrap_or(match source {
+            Some(ImageFormat::Jpeg) => OutputFormat::Jpeg,
+            _ => OutputFormat::Png,
+        })
+    }
+
+    /// Applies the transformation, refusing to resize beyond `max_dimension` on either side or
+    /// `max_pixels` in total, including a side derived from the aspect ratio.
+    pub fn apply(&self, mut image: DynamicImage, max_dimension: u32, max_pixels: u64) -> Result<DynamicImage, TransformError> {
+        if let Some([x, y, width, height]) = self.crop_area()? {
+            if x.saturating_add(width) > image.width() || y.saturating_add(height) > image.height() {
+                return Err(TransformError::Invalid(format!("crop area exceeds the {}x{} image", image.width(), image.height())));
+            }
+            image = image.crop_imm(x, y, width, height);
+        }
+        image = match self.rotate.unwrap_or(0) % 360 {
+            90 => image.rotate90(),
+            180 => image.rotate180(),
+            270 => image.rotate270(),
+            _ => image,
+        };
+        let size = match (self.width, self.height) {
+            (Some(width), Some(height)) => Some((width as u64, height as u64)),
+            (Some(width), None) => Some((width as u64, (image.height() as u64 * width as u64 / image.width() as u64).max(1))),
+            (None, Some(height)) => Some(((image.width() as u64 * height as u64 / image.height() as u64).max(1), height as u64)),
+            (None, None) => None,
+        };
+        if let Some((width, height)) = size {
+            if width > max_dimension as u64 || height > max_dimension as u64 {
+                return Err(TransformError::Invalid(format!("resizing to {}x{} exceeds the maximum dimension {}", width, height, max_dimension)));
+            }
+            if width * height > max_pixels {
+                return Err(TransformError::Invalid(format!("resizing to {}x{} exceeds {} pixels", width, height, max_pixels)));
+            }
+            image = image.resize_exact(width as u32, height as u32, FilterType::Lanczos3);
+        }
+        if let Some(edge) = self.thumbnail {
+            image = image.thumbnail(edge, edge);
+        }
+        Ok(image)
+    }
+
+    pub fn encode(&self, image: &DynamicImage, format: OutputFormat) -> Result<Vec<u8>, TransformError> {
+        let mut output = Cursor::new(Vec::new());
+        let result = match format {
+            OutputFormat::Png => image.write_to(&mut output, ImageOutputFormat::Png),
+            OutputFormat::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8())
+                .write_to(&mut output, ImageOutputFormat::Jpeg(self.quality.unwrap_or(DEFAULT_QUALITY))),
+            OutputFormat::Webp => return Err(TransformError::Unsupported("WebP output is not available in this build".to_string())),
+        };
+        result.map_err(|e| TransformError::Invalid(format!("could not encode image: {}", e)))?;
+        Ok(output.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, ImageFormat, RgbaImage};
+    use super::{OutputFormat, Transform, TransformError};
+
+    fn image() -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::new(200, 100))
+    }
+
+    #[test]
+    fn test_apply() {
+        let transform = Transform { crop: Some("10,0,100,50".to_string()), rotate: Some(90), width: Some(25), ..Default::default() };
+        let result = transform.apply(image(), 4096, 1_000_000).unwrap();
+        assert_eq!((result.width(), result.height()), (25, 50));
+        let thumbnail = Transform { thumbnail: Some(40), ..Default::default() }.apply(image(), 4096, 1_000_000).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (40, 20));
+        assert!(Transform { crop: Some("150,0,100,50".to_string()), ..Default::default() }.apply(image(), 4096, 1_000_000).is_err());
+    }
+
+    #[test]
+    fn test_apply_extreme_aspect_ratio() {
+        let tall = DynamicImage::ImageRgba8(RgbaImage::new(1, 10000));
+        let transform = Transform { width: Some(4000), ..Default::default() };
+        assert!(transform.validate(4096).is_ok());
+        assert!(matches!(transform.apply(tall.clone(), 4096, 25_000_000), Err(TransformError::Invalid(_))));
+        let wide = DynamicImage::ImageRgba8(RgbaImage::new(10000, 1));
+        assert!(matches!(Transform { height: Some(4000), ..Default::default() }.apply(wide, 4096, 25_000_000), Err(TransformError::Invalid(_))));
+        let exact = Transform { width: Some(4000), height: Some(4000), ..Default::default() };
+        assert!(matches!(exact.apply(tall, 4096, 1_000_000), Err(TransformError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(Transform { width: Some(4096), ..Default::default() }.validate(4096).is_ok());
+        assert!(Transform { width: Some(4097), ..Default::default() }.validate(4096).is_err());
+        assert!(Transform { rotate: Some(45), ..Default::default() }.validate(4096).is_err());
+        assert!(Transform { crop: Some("1,2,3".to_string()), ..Default::default() }.validate(4096).is_err());
+        assert!(matches!(Transform { format: Some(OutputFormat::Webp), ..Default::default() }.validate(4096), Err(TransformError::Unsupported(_))));
+    }
+
+    #[test]
+    fn test_encode() {
+        let transform = Transform { quality: Some(50), ..Default::default() };
+        let format = transform.output_format(Some(ImageFormat::Jpeg));
+        let encoded = transform.encode(&image(), format).unwrap();
+        assert_eq!(image::guess_format(&encoded).unwrap(), ImageFormat::Jpeg);
+        assert_eq!(Transform::default().output_format(Some(ImageFormat::Gif)), OutputFormat::Png);
+    }
+}
@@ -1,8 +1,10 @@
+use std::sync::Arc;
 use axum::Router;
 use axum_template::engine::Engine;
 use handlebars::Handlebars;
 use sqlx::PgPool;
 use tracing::info;
+use aggregate_cache::AggregateCache;
 
 mod day_minus1;
 mod day_01;
@@ -21,6 +23,23 @@ mod day_20;
 mod day_21;
 mod day_05;
 mod day_22;
+mod aggregate_cache;
+mod asset_store;
+mod clock;
+mod colour_analysis;
+mod database;
+mod html_sanitizer;
+mod image_cache;
+mod image_transform;
+mod pagination;
+mod phrase_counter;
+mod pokemon_client;
+mod recipe_encoding;
+mod reports;
+mod signed_cookie;
+mod timestamp_store;
+mod ulid_query;
+mod units;
 
 #[shuttle_runtime::main]
 async fn main(#[shuttle_shared_db::Postgres] pool: PgPool) -> shuttle_axum::ShuttleAxum {
@@ -39,6 +58,8 @@ async fn init_app_with_db(pool: PgPool) -> Result<Router, shuttle_runtime::Error
 }
 async fn init_app(pool: Option<PgPool>) -> Result<Router, shuttle_runtime::Error> {
 
+    // Days 13 and 18 share the orders table, so they share the cache invalidated by its changes.
+    let cache = Arc::new(AggregateCache::default());
     info!("Initializing router.");
     Ok(Router::new()
         .nest("/", day_minus1::router())
@@ -49,15 +70,16 @@ async fn init_app(pool: Option<PgPool>) -> Result<Router, shuttle_runtime::Error
         .nest("/7", day_07::router())
         .nest("/8", day_08::router())
         .nest("/11", day_11::router())
-        .nest("/12", day_12::router())
-        .nest("/13", day_13::router(pool.clone()))
+        .nest("/12", day_12::router(pool.clone()))
+        .nest("/13", day_13::router(pool.clone(), cache.clone()))
         .nest("/14", day_14::router())
         .nest("/15", day_15::router())
-        .nest("/18", day_18::router(pool.clone()))
+        .nest("/18", day_18::router(pool.clone(), cache.clone()))
         .nest("/19", day_19::router())
         .nest("/20", day_20::router())
         .nest("/21", day_21::router())
-        .nest("/22", day_22::router()))
+        .nest("/22", day_22::router())
+        .nest("/reports", reports::router(pool.clone())))
 
 }
 
@@ -68,7 +90,7 @@ mod tests {
     use axum::body::to_bytes;
     use tower::util::ServiceExt;
 
-    use crate::init_app;
+    use crate::{init_app, init_app_with_db};
 
     #[tokio::test]
     async fn test_app() {
@@ -83,24 +105,41 @@ mod tests {
         assert_eq!(body_string, "Hello, world!");
     }
 
+    /// Issues a signed recipe cookie through `/7/cookie` and returns it as `recipe=...`.
+    async fn recipe_cookie(app: &axum::Router, recipe: &str) -> String {
+        let response = app.clone()
+            .oneshot(Request::builder().method("POST").uri("/7/cookie").header("content-type", "application/json").body(Body::from(recipe.to_string())).unwrap())
+            .await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let set_cookie = response.headers().get("set-cookie").unwrap().to_str().unwrap();
+        set_cookie.split(';').next().unwrap().to_string()
+    }
+
     #[tokio::test]
     async fn test_day07() {
         let app = init_app(None).await.unwrap();
-        let response = app
-            .oneshot(Request::builder().uri("/7/decode").header("cookie", "recipe=eyJmbG91ciI6MTAwLCJjaG9jb2xhdGUgY2hpcHMiOjIwfQ==").body(Body::empty()).unwrap())
+        let cookie = recipe_cookie(&app, "{\"flour\":100,\"chocolate chips\":20}").await;
+        let response = app.clone()
+            .oneshot(Request::builder().uri("/7/decode").header("cookie", cookie).body(Body::empty()).unwrap())
             .await.unwrap();
 
         assert_eq!(response.status(), StatusCode::OK);
         let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
-        let body_string = std::str::from_utf8( &body).unwrap();
-        assert_eq!(body_string, "{\"flour\":100,\"chocolate chips\":20}");
+        let recipe: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(recipe, serde_json::json!({"flour": 100, "chocolate chips": 20}));
+
+        let response = app
+            .oneshot(Request::builder().uri("/7/decode").header("cookie", "recipe=eyJmbG91ciI6MTAwLCJjaG9jb2xhdGUgY2hpcHMiOjIwfQ==").body(Body::empty()).unwrap())
+            .await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
     }
 
     #[tokio::test]
     async fn test_day07_bake() {
         let app = init_app(None).await.unwrap();
+        let cookie = recipe_cookie(&app, "{\"recipe\":{\"flour\":95,\"sugar\":50,\"butter\":30,\"baking powder\":10,\"chocolate chips\":50},\"pantry\":{\"flour\":385,\"sugar\":507,\"butter\":2122,\"baking powder\":865,\"chocolate chips\":457}}").await;
         let response = app
-            .oneshot(Request::builder().uri("/7/bake").header("cookie", "recipe=eyJyZWNpcGUiOnsiZmxvdXIiOjk1LCJzdWdhciI6NTAsImJ1dHRlciI6MzAsImJha2luZyBwb3dkZXIiOjEwLCJjaG9jb2xhdGUgY2hpcHMiOjUwfSwicGFudHJ5Ijp7ImZsb3VyIjozODUsInN1Z2FyIjo1MDcsImJ1dHRlciI6MjEyMiwiYmFraW5nIHBvd2RlciI6ODY1LCJjaG9jb2xhdGUgY2hpcHMiOjQ1N319").body(Body::empty()).unwrap())
+            .oneshot(Request::builder().uri("/7/bake").header("cookie", cookie).body(Body::empty()).unwrap())
             .await.unwrap();
 
         assert_eq!(response.status(), StatusCode::OK);
@@ -113,4 +152,331 @@ mod tests {
         assert!(body_string.contains("baking powder\":825"));
         assert!(body_string.contains("chocolate chips\":257"));
     }
+
+    #[tokio::test]
+    async fn test_day07_signed_cookie() {
+        let app = init_app(None).await.unwrap();
+        let recipe = "{\"recipe\":{\"flour\":95},\"pantry\":{\"flour\":385}}";
+        let response = app.clone()
+            .oneshot(Request::builder().method("POST").uri("/7/cookie").header("content-type", "application/json").body(Body::from(recipe)).unwrap())
+            .await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let set_cookie = response.headers().get("set-cookie").unwrap().to_str().unwrap();
+        let cookie = set_cookie.split(';').next().unwrap().to_string();
+        let response = app.clone()
+            .oneshot(Request::builder().uri("/7/bake").header("cookie", &cookie).body(Body::empty()).unwrap())
+            .await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(std::str::from_utf8(&body).unwrap().contains("cookies\":4"));
+
+        let tampered = cookie.replacen("recipe=eyJ", "recipe=eyK", 1);
+        assert_ne!(tampered, cookie);
+        let response = app
+            .oneshot(Request::builder().uri("/7/bake").header("cookie", tampered).body(Body::empty()).unwrap())
+            .await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_day07_decode_sources() {
+        let app = init_app(None).await.unwrap();
+        let cookie = recipe_cookie(&app, "{\"flour\":100}").await;
+        let recipe = cookie.strip_prefix("recipe=").unwrap().to_string();
+        let response = app.clone()
+            .oneshot(Request::builder().uri(format!("/7/decode?encoding=base64&recipe={}", recipe.replace('=', "%3D"))).body(Body::empty()).unwrap())
+            .await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(std::str::from_utf8(&body).unwrap(), "{\"flour\":100}");
+
+        let response = app.clone()
+            .oneshot(Request::builder().method("POST").uri("/7/decode").body(Body::from(recipe)).unwrap())
+            .await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .oneshot(Request::builder().method("POST").uri("/7/decode").body(Body::from("eyJmbG91ciI6MTAwfQ%3D%3D")).unwrap())
+            .await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(std::str::from_utf8(&body).unwrap(), "cookie is not signed");
+    }
+
+    #[tokio::test]
+    async fn test_day11_analysis() {
+        let app = init_app(None).await.unwrap();
+        let image = std::fs::read("assets/decoration.png").unwrap();
+        let mut body = b"--boundary\r\nContent-Disposition: form-data; name=\"image\"; filename=\"decoration.png\"\r\nContent-Type: image/png\r\n\r\n".to_vec();
+        body.extend_from_slice(&image);
+        body.extend_from_slice(b"\r\n--boundary\r\nContent-Disposition: form-data; name=\"options\"\r\n\r\n{\"histogram\":false,\"dominant\":3}\r\n--boundary--\r\n");
+        let response = app
+            .oneshot(Request::builder().method("POST").uri("/11/analysis").header("content-type", "multipart/form-data; boundary=boundary").body(Body::from(body)).unwrap())
+            .await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let analysis: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(analysis["matching"], 73034);
+        assert_eq!(analysis["dominant"].as_array().unwrap().len(), 3);
+        assert!(analysis.get("histograms").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_day11_red_pixels_uploads() {
+        let app = init_app(None).await.unwrap();
+        let image = std::fs::read("assets/decoration.png").unwrap();
+        let mut body = Vec::new();
+        for filename in ["first.png", "second.png"] {
+            body.extend_from_slice(format!("--boundary\r\nContent-Disposition: form-data; name=\"image\"; filename=\"{}\"\r\n\r\n", filename).as_bytes());
+            body.extend_from_slice(&image);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(b"--boundary--\r\n");
+        let response = app.clone()
+            .oneshot(Request::builder().method("POST").uri("/11/red_pixels").header("content-type", "multipart/form-data; boundary=boundary").body(Body::from(body)).unwrap())
+            .await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(std::str::from_utf8(&body).unwrap(), "[{\"filename\":\"first.png\",\"red_pixels\":73034},{\"filename\":\"second.png\",\"red_pixels\":73034}]");
+
+        let body = "--boundary\r\nContent-Disposition: form-data; name=\"image\"\r\n\r\nnot an image\r\n--boundary--\r\n";
+        let response = app
+            .oneshot(Request::builder().method("POST").uri("/11/red_pixels").header("content-type", "multipart/form-data; boundary=boundary").body(Body::from(body)).unwrap())
+            .await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[tokio::test]
+    async fn test_day11_asset_transform() {
+        let app = init_app(None).await.unwrap();
+        let response = app.clone()
+            .oneshot(Request::builder().uri("/11/assets/decoration.png").body(Body::empty()).unwrap())
+            .await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get("content-type").unwrap(), "image/png");
+
+        let response = app.clone()
+            .oneshot(Request::builder().uri("/11/assets/decoration.png?width=100&format=jpeg&quality=70").body(Body::empty()).unwrap())
+            .await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get("content-type").unwrap(), "image/jpeg");
+        assert_eq!(response.headers().get("cache-control").unwrap(), "public, max-age=86400");
+        let etag = response.headers().get("etag").unwrap().clone();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(image::load_from_memory(&body).unwrap().width(), 100);
+
+        let response = app.clone()
+            .oneshot(Request::builder().uri("/11/assets/decoration.png?width=100&format=jpeg&quality=70").header("if-none-match", etag).body(Body::empty()).unwrap())
+            .await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        let response = app
+            .oneshot(Request::builder().uri("/11/assets/decoration.png?width=100000").body(Body::empty()).unwrap())
+            .await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_day11_asset_management_disabled() {
+        let app = init_app(None).await.unwrap();
+        let response = app
+            .oneshot(Request::builder().method("DELETE").uri("/11/admin/assets/decoration.png").header("authorization", "Bearer secret").body(Body::empty()).unwrap())
+            .await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(std::path::Path::new("assets/decoration.png").exists());
+    }
+
+    #[tokio::test]
+    async fn test_day12_packets() {
+        let app = init_app(None).await.unwrap();
+        let response = app.clone()
+            .oneshot(Request::builder().method("POST").uri("/12/save/packet20231212").body(Body::empty()).unwrap())
+            .await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app.clone()
+            .oneshot(Request::builder().uri("/12/packets").body(Body::empty()).unwrap())
+            .await.unwrap();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let packets: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(packets[0]["key"], "packet20231212");
+        assert_eq!(packets[0]["expires_at"], serde_json::Value::Null);
+
+        let response = app.clone()
+            .oneshot(Request::builder().method("DELETE").uri("/12/packets/packet20231212").body(Body::empty()).unwrap())
+            .await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let response = app
+            .oneshot(Request::builder().uri("/12/load/packet20231212").body(Body::empty()).unwrap())
+            .await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_day12_admin_clock() {
+        let app = init_app(None).await.unwrap();
+        let command = |body: &'static str| Request::builder().method("POST").uri("/12/admin/clock").header("content-type", "application/json").body(Body::from(body)).unwrap();
+        let response = app.clone().oneshot(command("{\"action\":\"freeze\"}")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        app.clone()
+            .oneshot(Request::builder().method("POST").uri("/12/save/packet").body(Body::empty()).unwrap())
+            .await.unwrap();
+        app.clone().oneshot(command("{\"action\":\"advance\",\"seconds\":5}")).await.unwrap();
+
+        let response = app
+            .oneshot(Request::builder().uri("/12/load/packet").body(Body::empty()).unwrap())
+            .await.unwrap();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(std::str::from_utf8(&body).unwrap(), "5");
+    }
+
+    #[tokio::test]
+    async fn test_reports_without_database() {
+        let app = init_app(None).await.unwrap();
+        let response = app.clone()
+            .oneshot(Request::builder().uri("/reports").body(Body::empty()).unwrap())
+            .await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(std::str::from_utf8(&body).unwrap().contains("\"gift_totals\""));
+
+        let response = app.clone()
+            .oneshot(Request::builder().uri("/reports/unknown").body(Body::empty()).unwrap())
+            .await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = app.clone()
+            .oneshot(Request::builder().uri("/reports/gift_totals?limit=many").body(Body::empty()).unwrap())
+            .await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = app
+            .oneshot(Request::builder().uri("/reports/gift_totals?format=csv").body(Body::empty()).unwrap())
+            .await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_cache_metrics() {
+        let app = init_app(None).await.unwrap();
+        for uri in ["/13/cache/metrics", "/18/cache/metrics"] {
+            let response = app.clone()
+                .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+                .await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            assert_eq!(std::str::from_utf8(&body).unwrap(), "{\"entries\":0,\"hits\":0,\"misses\":0,\"hit_rate\":0.0,\"invalidations\":0}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_day18_top_list_limits() {
+        let app = init_app(None).await.unwrap();
+        for uri in ["/18/regions/top_list/-1", "/18/regions/top_list/101", "/18/regions/top_list/2147483647"] {
+            let response = app.clone()
+                .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+                .await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_order_aggregates_without_database() {
+        let app = init_app(None).await.unwrap();
+        for uri in ["/13/orders/total", "/13/orders/popular", "/18/regions/total", "/18/regions/top_list/2"] {
+            let response = app.clone()
+                .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+                .await.unwrap();
+            assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE, "{}", uri);
+        }
+        let response = app
+            .oneshot(Request::builder().method("POST").uri("/13/reset").body(Body::empty()).unwrap())
+            .await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn test_order_aggregates_empty_state() {
+        let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL points to a Postgres database");
+        let pool = sqlx::PgPool::connect(&url).await.unwrap();
+        let app = init_app_with_db(pool.clone()).await.unwrap();
+        let get = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap();
+                let status = response.status();
+                let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (status, String::from_utf8(body.to_vec()).unwrap())
+            }
+        };
+        let post = |uri: &'static str, body: &'static str| {
+            let app = app.clone();
+            async move {
+                app.oneshot(Request::builder().method("POST").uri(uri).header("content-type", "application/json").body(Body::from(body)).unwrap())
+                    .await.unwrap()
+                    .status()
+            }
+        };
+
+        sqlx::query("DROP TABLE IF EXISTS orders, regions").execute(&pool).await.unwrap();
+        for uri in ["/13/orders/total", "/13/orders/popular", "/18/regions/total", "/18/regions/top_list/2"] {
+            assert_eq!(get(uri).await.0, StatusCode::NOT_FOUND, "{}", uri);
+        }
+        assert_eq!(post("/13/orders", "[{\"id\":1,\"region_id\":1,\"gift_name\":\"Toy\",\"quantity\":1}]").await, StatusCode::NOT_FOUND);
+
+        assert_eq!(post("/18/reset", "").await, StatusCode::OK);
+        assert_eq!(get("/13/orders/total").await, (StatusCode::OK, "{\"total\":0}".to_string()));
+        assert_eq!(get("/13/orders/popular").await, (StatusCode::OK, "{\"popular\":null}".to_string()));
+        assert_eq!(get("/18/regions/total").await, (StatusCode::OK, "[]".to_string()));
+        assert_eq!(get("/18/regions/top_list/2").await, (StatusCode::OK, "[]".to_string()));
+
+        assert_eq!(post("/18/regions", "[{\"id\":1,\"name\":\"North\"}]").await, StatusCode::OK);
+        assert_eq!(get("/18/regions/total").await, (StatusCode::OK, "[]".to_string()));
+        assert_eq!(get("/18/regions/top_list/2").await, (StatusCode::OK, "[{\"region\":\"North\",\"top_gifts\":[]}]".to_string()));
+
+        assert_eq!(post("/18/orders", "[{\"id\":1,\"region_id\":1,\"gift_name\":\"Toy\",\"quantity\":1}]").await, StatusCode::OK);
+        assert_eq!(post("/18/orders", "[{\"id\":1,\"region_id\":1,\"gift_name\":\"Toy\",\"quantity\":1}]").await, StatusCode::BAD_REQUEST);
+        assert_eq!(post("/18/reset", "").await, StatusCode::OK);
+
+        sqlx::query("ALTER TABLE orders ALTER COLUMN quantity TYPE TEXT").execute(&pool).await.unwrap();
+        assert_eq!(post("/13/orders", "[]").await, StatusCode::OK);
+        assert_eq!(get("/13/orders/total").await.0, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(get("/18/regions/total").await.0, StatusCode::INTERNAL_SERVER_ERROR);
+
+        sqlx::query("ALTER TABLE orders DROP COLUMN gift_name").execute(&pool).await.unwrap();
+        assert_eq!(post("/13/orders", "[{\"id\":2,\"region_id\":1,\"gift_name\":\"Toy\",\"quantity\":1}]").await, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(post("/13/reset", "").await, StatusCode::OK);
+        assert_eq!(get("/13/sql").await, (StatusCode::OK, "20231213".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_day14_sanitized() {
+        let app = init_app(None).await.unwrap();
+        let request = |uri: &str, body: &'static str| Request::builder().method("POST").uri(uri).header("content-type", "application/json").body(Body::from(body)).unwrap();
+        let response = app.clone()
+            .oneshot(request("/14/sanitized", "{\"content\":\"<p onclick=\\\"x()\\\">Ho <a href=\\\"javascript:x()\\\">ho</a></p><script>x()</script>\"}"))
+            .await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers()["content-security-policy"].to_str().unwrap().starts_with("default-src 'none'"));
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(std::str::from_utf8(&body).unwrap().contains("<p>Ho <a rel=\"noopener noreferrer\">ho</a></p>\n"));
+
+        let response = app.clone()
+            .oneshot(request("/14/unsafe", "{}"))
+            .await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(response.headers().contains_key("content-security-policy"));
+
+        for (method, uri) in [("POST", "/14/missing"), ("GET", "/14/unsafe"), ("GET", "/14")] {
+            let response = app.clone()
+                .oneshot(Request::builder().method(method).uri(uri).body(Body::empty()).unwrap())
+                .await.unwrap();
+            assert!(response.status().is_client_error(), "{} {}", method, uri);
+            assert!(response.headers().contains_key("content-security-policy"), "{} {}", method, uri);
+        }
+    }
 }
@@ -0,0 +1,206 @@
+use std::fmt;
+use std::str::FromStr;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode, Uri};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use serde::{de, Deserialize, Deserializer};
+use tracing::info;
+
+const CURSOR_PREFIX: &str = "offset:";
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PageParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub offset: Option<i64>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub limit: Option<usize>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub split: Option<usize>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub cursor: Option<String>,
+}
+
+pub fn empty_string_as_none<'de, D, T>(de: D) -> Result<Option<T>, D::Error>
+    where
+        D: Deserializer<'de>,
+        T: FromStr,
+        T::Err: fmt::Display,
+{
+    let opt = Option::<String>::deserialize(de)?;
+    match opt.as_deref() {
+        None | Some("") => Ok(None),
+        Some(s) => FromStr::from_str(s).map_err(de::Error::custom).map(Some),
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Page {
+    pub offset: usize,
+    pub end: usize,
+    pub limit: usize,
+    pub total: usize,
+    pub split: Option<usize>,
+}
+
+impl PageParams {
+    /// Resolves the parameters against a list of `total` items. Negative offsets count from the
+    /// end of the list, offsets past the end yield an empty page and a zero split is rejected.
+    pub fn paginate(&self, total: usize) -> Result<Page, StatusCode> {
+        let offset = self.start()?;
+        let offset = if offset < 0 {
+            total.saturating_sub(offset.unsigned_abs() as usize)
+        } else {
+            (offset as usize).min(total)
+        };
+        let limit = self.limit.unwrap_or(total);
+        let end = offset.saturating_add(limit).min(total);
+        Ok(Page { offset, end, limit, total, split: self.split })
+    }
+
+    /// Resolves offset and limit for a list of unknown length, e.g. while streaming. Offsets
+    /// relative to the end can not be resolved without the total and are rejected.
+    pub fn paginate_unbounded(&self) -> Result<(usize, Option<usize>), StatusCode> {
+        let offset = self.start()?;
+        if offset < 0 {
+            info!("Rejecting negative offset for a list of unknown length.");
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        Ok((offset as usize, self.limit))
+    }
+
+    fn start(&self) -> Result<i64, StatusCode> {
+        if self.split == Some(0) {
+            info!("Rejecting pagination with split of zero.");
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        match &self.cursor {
+            Some(cursor) => decode_cursor(cursor),
+            None => Ok(self.offset.unwrap_or(0)),
+        }
+    }
+}
+
+impl Page {
+    pub fn slice<'a, T>(&self, items: &'a [T]) -> &'a [T] {
+        &items[self.offset..self.end]
+    }
+
+    pub fn next_cursor(&self) -> Option<String> {
+        if self.end < self.total && self.limit > 0 {
+            Some(encode_cursor(self.end))
+        } else {
+            None
+        }
+    }
+
+    pub fn prev_cursor(&self) -> Option<String> {
+        if self.offset > 0 && self.limit > 0 {
+            Some(encode_cursor(self.offset.saturating_sub(self.limit)))
+        } else {
+            None
+        }
+    }
+
+    /// `X-Total-Count` and `Link` headers pointing to the neighbouring pages of `uri`.
+    pub fn headers(&self, uri: &Uri) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(HeaderName::from_static("x-total-count"), HeaderValue::from(self.total));
+        let links: Vec<String> = [("next", self.next_cursor()), ("prev", self.prev_cursor())].into_iter()
+            .filter_map(|(rel, cursor)| cursor.map(|cursor| format!("<{}>; rel=\"{}\"", self.link(uri, &cursor), rel)))
+            .collect();
+        if !links.is_empty() {
+            if let Ok(value) = HeaderValue::from_str(&links.join(", ")) {
+                headers.insert(axum::http::header::LINK, value);
+            }
+        }
+        headers
+    }
+
+    fn link(&self, uri: &Uri, cursor: &str) -> String {
+        let mut query: Vec<String> = uri.query().unwrap_or("")
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .filter(|pair| {
+                let key = pair.split('=').next().unwrap_or("");
+                key != "offset" && key != "cursor" && key != "limit"
+            })
+            .map(|pair| pair.to_string())
+            .collect();
+        query.push(format!("limit={}", self.limit));
+        query.push(format!("cursor={}", cursor));
+        format!("{}?{}", uri.path(), query.join("&"))
+    }
+}
+
+pub fn split<T: Clone>(items: &[T], split: usize) -> Vec<Vec<T>> {
+    items.chunks(split).map(|chunk| chunk.to_vec()).collect()
+}
+
+fn encode_cursor(offset: usize) -> String {
+    URL_SAFE_NO_PAD.encode(format!("{}{}", CURSOR_PREFIX, offset))
+}
+
+fn decode_cursor(cursor: &str) -> Result<i64, StatusCode> {
+    let decoded = URL_SAFE_NO_PAD.decode(cursor).map_err(|_| StatusCode::BAD_REQUEST)?;
+    String::from_utf8(decoded).ok()
+        .and_then(|cursor| cursor.strip_prefix(CURSOR_PREFIX).and_then(|offset| offset.parse::<usize>().ok()))
+        .map(|offset| offset as i64)
+        .ok_or(StatusCode::BAD_REQUEST)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{StatusCode, Uri};
+    use super::{Page, PageParams};
+
+    fn params(offset: Option<i64>, limit: Option<usize>, split: Option<usize>) -> PageParams {
+        PageParams { offset, limit, split, cursor: None }
+    }
+
+    #[test]
+    fn test_paginate_clamps_offset() {
+        let page = params(Some(10), Some(3), None).paginate(5).unwrap();
+        assert_eq!((page.offset, page.end), (5, 5));
+        assert!(page.slice(&[1, 2, 3, 4, 5]).is_empty());
+    }
+
+    #[test]
+    fn test_paginate_negative_offset() {
+        let page = params(Some(-2), None, None).paginate(5).unwrap();
+        assert_eq!(page.slice(&[1, 2, 3, 4, 5]), &[4, 5]);
+        let page = params(Some(-10), Some(1), None).paginate(5).unwrap();
+        assert_eq!(page.slice(&[1, 2, 3, 4, 5]), &[1]);
+    }
+
+    #[test]
+    fn test_paginate_zero_split() {
+        assert_eq!(params(None, None, Some(0)).paginate(5), Err(StatusCode::BAD_REQUEST));
+    }
+
+    #[test]
+    fn test_paginate_cursor() {
+        let page = params(Some(1), Some(2), None).paginate(5).unwrap();
+        let next = PageParams { cursor: page.next_cursor(), ..params(None, Some(2), None) }.paginate(5).unwrap();
+        assert_eq!(next.slice(&[1, 2, 3, 4, 5]), &[4, 5]);
+        assert_eq!(next.next_cursor(), None);
+        let invalid = PageParams { cursor: Some("nope".to_string()), ..Default::default() };
+        assert_eq!(invalid.paginate(5), Err(StatusCode::BAD_REQUEST));
+    }
+
+    #[test]
+    fn test_paginate_unbounded() {
+        assert_eq!(params(Some(2), Some(3), None).paginate_unbounded(), Ok((2, Some(3))));
+        assert_eq!(params(Some(-2), None, None).paginate_unbounded(), Err(StatusCode::BAD_REQUEST));
+    }
+
+    #[test]
+    fn test_headers() {
+        let page = Page { offset: 2, end: 4, limit: 2, total: 6, split: Some(2) };
+        let headers = page.headers(&Uri::from_static("/5?offset=2&limit=2&split=2"));
+        assert_eq!(headers.get("x-total-count").unwrap(), "6");
+        let link = headers.get("link").unwrap().to_str().unwrap();
+        assert!(link.contains("/5?split=2&limit=2&cursor="));
+        assert!(link.contains("rel=\"next\""));
+        assert!(link.contains("rel=\"prev\""));
+    }
+}
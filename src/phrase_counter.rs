@@ -0,0 +1,271 @@
+use std::collections::BTreeMap;
+use aho_corasick::{AhoCorasick, AhoCorasickBuilder, MatchKind};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Phrase {
+    pub name: String,
+    pub pattern: String,
+    /// Matches lying completely inside a match of one of these phrases are not counted.
+    #[serde(default)]
+    pub exclude_within: Vec<String>,
+}
+
+impl Phrase {
+    pub fn new(name: &str, pattern: &str, exclude_within: &[&str]) -> Phrase {
+        Phrase {
+            name: name.to_string(),
+            pattern: pattern.to_string(),
+            exclude_within: exclude_within.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug)]
+pub struct MatchOptions {
+    #[serde(default)]
+    pub case_insensitive: bool,
+    #[serde(default)]
+    pub whole_word: bool,
+    #[serde(default = "default_overlapping")]
+    pub overlapping: bool,
+}
+
+fn default_overlapping() -> bool {
+    true
+}
+
+impl Default for MatchOptions {
+    fn default() -> MatchOptions {
+        MatchOptions { case_insensitive: false, whole_word: false, overlapping: default_overlapping() }
+    }
+}
+
+#[derive(Serialize, Debug, Default, Eq, PartialEq)]
+pub struct PhraseCounts {
+    pub counts: BTreeMap<String, usize>,
+    pub matches: BTreeMap<String, Vec<(usize, usize)>>,
+}
+
+pub struct PhraseCounter {
+    phrases: Vec<Phrase>,
+    options: MatchOptions,
+    automaton: AhoCorasick,
+    max_len: usize,
+    exclude_within: Vec<Vec<usize>>,
+}
+
+impl PhraseCounter {
+    pub fn new(phrases: Vec<Phrase>, options: MatchOptions) -> Result<PhraseCounter, String> {
+        if let Some(phrase) = phrases.iter().find(|phrase| phrase.pattern.is_empty()) {
+            return Err(format!("Phrase {} has an empty pattern", phrase.name));
+        }
+        if let Some(unknown) = phrases.iter()
+            .flat_map(|phrase| phrase.exclude_within.iter())
+            .find(|name| !phrases.iter().any(|phrase| &phrase.name == *name)) {
+            return Err(format!("Unknown phrase {} in exclude_within", unknown));
+        }
+        let automaton = AhoCorasickBuilder::new()
+            .match_kind(MatchKind::Standard)
+            .ascii_case_insensitive(options.case_insensitive)
+            .build(phrases.iter().map(|phrase| &phrase.pattern))
+            .map_err(|e| e.to_string())?;
+        let max_len = phrases.iter().map(|phrase| phrase.pattern.len()).max().unwrap_or(0);
+        let exclude_within = phrases.iter()
+            .map(|phrase| phrases.iter().enumerate()
+                .filter(|(_, other)| phrase.exclude_within.contains(&other.name))
+                .map(|(index, _)| index)
+                .collect())
+            .collect();
+        Ok(PhraseCounter { phrases, options, automaton, max_len, exclude_within })
+    }
+
+    pub fn elf_counter() -> PhraseCounter {
+        PhraseCounter::new(vec![
+            Phrase::new("elf", "elf", &[]),
+            Phrase::new("elf on a shelf", "elf on a shelf", &[]),
+            Phrase::new("shelf with no elf on it", "shelf", &["elf on a shelf"]),
+        ], MatchOptions::default()).expect("Elf phrases are valid")
+    }
+
+    /// Finds all phrases in `text`, returning counts and byte offsets per phrase.
+    pub fn count(&self, text: &str) -> PhraseCounts {
+        let mut scanner = self.scanner(true);
+        scanner.feed(text.as_bytes());
+        scanner.finish()
+    }
+
+    /// Creates a scanner for text arriving in chunks. Offsets are only collected if
+    /// `record_matches` is set, so counting large documents needs constant memory.
+    pub fn scanner(&self, record_matches: bool) -> PhraseScanner<'_> {
+        let mut result = PhraseCounts::default();
+        for phrase in &self.phrases {
+            result.counts.insert(phrase.name.clone(), 0);
+            if record_matches {
+                result.matches.insert(phrase.name.clone(), Vec::new());
+            }
+        }
+        PhraseScanner {
+            counter: self,
+            buffer: Vec::new(),
+            buffer_offset: 0,
+            committed: 0,
+            last_end: vec![None; self.phrases.len()],
+            record_matches,
+            result,
+        }
+    }
+}
+
+pub struct PhraseScanner<'a> {
+    counter: &'a PhraseCounter,
+    buffer: Vec<u8>,
+    buffer_offset: usize,
+    committed: usize,
+    last_end: Vec<Option<usize>>,
+    record_matches: bool,
+    result: PhraseCounts,
+}
+
+impl PhraseScanner<'_> {
+    pub fn feed(&mut self, chunk: &[u8]) {
+        self.buffer.extend_from_slice(chunk);
+        self.scan(false);
+    }
+
+    pub fn finish(mut self) -> PhraseCounts {
+        self.scan(true);
+        self.result
+    }
+
+    /// Decides all matches starting before a horizon far enough from the end of the buffer that
+    /// surrounding characters and enclosing phrases are known, then drops what is no longer needed.
+    fn scan(&mut self, last: bool) {
+        let max_len = self.counter.max_len;
+        let buffer_end = self.buffer_offset + self.buffer.len();
+        let limit = if last { buffer_end } else { buffer_end.saturating_sub(max_len + 1) };
+        if limit <= self.committed {
+            return;
+        }
+        let mut spans: Vec<(usize, usize, usize)> = self.counter.automaton.find_overlapping_iter(&self.buffer[..])
+            .map(|found| (found.pattern().as_usize(), found.start(), found.end()))
+            .filter(|(_, start, end)| !self.counter.options.whole_word || self.is_whole_word(*start, *end))
+            .collect();
+        spans.sort_by_key(|&(index, start, end)| (start, end, index));
+        let enclosing = EnclosingSpans::new(self.counter.phrases.len(), &spans);
+        for &(index, start, end) in &spans {
+            let (absolute_start, absolute_end) = (self.buffer_offset + start, self.buffer_offset + end);
+            if absolute_start < self.committed || absolute_start >= limit {
+                continue;
+            }
+            if !self.counter.options.overlapping && self.last_end[index].map(|last| last > absolute_start).unwrap_or(false) {
+                continue;
+            }
+            let phrase = &self.counter.phrases[index];
+            let excluded = self.counter.exclude_within[index].iter()
+                .any(|&other| enclosing.encloses(other, start, end));
+            if excluded {
+                continue;
+            }
+            self.last_end[index] = Some(absolute_end);
+            *self.result.counts.entry(phrase.name.clone()).or_default() += 1;
+            if self.record_matches {
+                self.result.matches.entry(phrase.name.clone()).or_default().push((absolute_start, absolute_end));
+            }
+        }
+        self.committed = limit;
+        let keep_from = limit.saturating_sub(max_len + 4).max(self.buffer_offset);
+        self.buffer.drain(..keep_from - self.buffer_offset);
+        self.buffer_offset = keep_from;
+    }
+
+    fn is_whole_word(&self, start: usize, end: usize) -> bool {
+        let before = (1..=start.min(4))
+            .find_map(|len| std::str::from_utf8(&self.buffer[start - len..start]).ok())
+            .and_then(|s| s.chars().next_back());
+        let after = (1..=(self.buffer.len() - end).min(4))
+            .find_map(|len| std::str::from_utf8(&self.buffer[end..end + len]).ok())
+            .and_then(|s| s.chars().next());
+        !before.map(char::is_alphanumeric).unwrap_or(false) && !after.map(char::is_alphanumeric).unwrap_or(false)
+    }
+}
+
+/// Spans per phrase sorted by start, each with the largest end of any span of that phrase starting
+/// no later, so whether a match lies inside one of them is a binary search instead of a scan.
+struct EnclosingSpans {
+    spans: Vec<Vec<(usize, usize)>>,
+}
+
+impl EnclosingSpans {
+    fn new(phrases: usize, sorted: &[(usize, usize, usize)]) -> EnclosingSpans {
+        let mut spans: Vec<Vec<(usize, usize)>> = vec![Vec::new(); phrases];
+        for &(index, start, end) in sorted {
+            let max_end = spans[index].last().map(|&(_, max_end)| max_end.max(end)).unwrap_or(end);
+            spans[index].push((start, max_end));
+        }
+        EnclosingSpans { spans }
+    }
+
+    fn encloses(&self, index: usize, start: usize, end: usize) -> bool {
+        let spans = &self.spans[index];
+        let starting_before = spans.partition_point(|&(other_start, _)| other_start <= start);
+        starting_before > 0 && spans[starting_before - 1].1 >= end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MatchOptions, Phrase, PhraseCounter};
+
+    #[test]
+    fn test_elf_counter() {
+        let counts = PhraseCounter::elf_counter().count("In Belfast I heard an elf on a shelf on a shelf on a");
+        assert_eq!(counts.counts["elf"], 4);
+        assert_eq!(counts.counts["elf on a shelf"], 2);
+        assert_eq!(counts.counts["shelf with no elf on it"], 0);
+        assert_eq!(counts.matches["elf on a shelf"], vec![(22, 36), (33, 47)]);
+    }
+
+    #[test]
+    fn test_options() {
+        let phrases = vec![Phrase::new("aa", "aa", &[]), Phrase::new("elf", "elf", &[])];
+        let text = "aaaa ELF Belfast elf";
+        let default = PhraseCounter::new(phrases.clone(), MatchOptions::default()).unwrap().count(text);
+        assert_eq!((default.counts["aa"], default.counts["elf"]), (3, 2));
+        let options = MatchOptions { case_insensitive: true, whole_word: false, overlapping: false };
+        let strict = PhraseCounter::new(phrases.clone(), options).unwrap().count(text);
+        assert_eq!((strict.counts["aa"], strict.counts["elf"]), (2, 3));
+        let options = MatchOptions { case_insensitive: true, whole_word: true, overlapping: true };
+        let words = PhraseCounter::new(phrases, options).unwrap().count(text);
+        assert_eq!((words.counts["aa"], words.counts["elf"]), (0, 2));
+    }
+
+    #[test]
+    fn test_scanner_chunks() {
+        let counter = PhraseCounter::elf_counter();
+        let text = "there is an elf on a shelf on an elf. there is also another shelf in Belfast.";
+        let expected = counter.count(text);
+        for chunk_size in [1, 2, 5, 13, 100] {
+            let mut scanner = counter.scanner(true);
+            text.as_bytes().chunks(chunk_size).for_each(|chunk| scanner.feed(chunk));
+            assert_eq!(scanner.finish(), expected);
+        }
+        assert_eq!(expected.counts["shelf with no elf on it"], 1);
+    }
+
+    #[test]
+    fn test_many_enclosing_matches() {
+        let phrases = vec![Phrase::new("a", "a", &["aa"]), Phrase::new("aa", "aa", &[]), Phrase::new("ab", "ab", &["aa"])];
+        let counter = PhraseCounter::new(phrases, MatchOptions::default()).unwrap();
+        let counts = counter.count(&format!("{}b", "a".repeat(200_000)));
+        assert_eq!(counts.counts["aa"], 199_999);
+        assert_eq!(counts.counts["a"], 0);
+        assert_eq!(counts.counts["ab"], 1);
+    }
+
+    #[test]
+    fn test_invalid_phrases() {
+        assert!(PhraseCounter::new(vec![Phrase::new("empty", "", &[])], MatchOptions::default()).is_err());
+        assert!(PhraseCounter::new(vec![Phrase::new("shelf", "shelf", &["elf"])], MatchOptions::default()).is_err());
+    }
+}
@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use axum::http::StatusCode;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Pokemon {
+    pub name: String,
+    pub id: i32,
+    pub weight: i32,
+    /// Height in decimetres, as reported by PokeAPI.
+    #[serde(default)]
+    pub height: i32,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum PokemonError {
+    InvalidId,
+    NotFound,
+    Upstream(String),
+}
+
+/// Normalizes a Pokémon id or name, rejecting anything that could not be a PokeAPI path segment.
+pub fn normalize_id(id: &str) -> Result<String, PokemonError> {
+    let id = id.trim().to_lowercase();
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err(PokemonError::InvalidId);
+    }
+    if id.chars().all(|c| c.is_ascii_digit()) {
+        let trimmed = id.trim_start_matches('0');
+        return Ok(if trimmed.is_empty() { "0".to_string() } else { trimmed.to_string() });
+    }
+    Ok(id)
+}
+
+impl From<PokemonError> for StatusCode {
+    fn from(error: PokemonError) -> StatusCode {
+        match error {
+            PokemonError::InvalidId => StatusCode::BAD_REQUEST,
+            PokemonError::NotFound => StatusCode::NOT_FOUND,
+            PokemonError::Upstream(_) => StatusCode::BAD_GATEWAY,
+        }
+    }
+}
+
+pub trait PokemonClient: Send + Sync {
+    fn get_pokemon<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Pokemon, PokemonError>>;
+}
+
+/// Builds the client from the environment: `POKEAPI_FIXTURES` points to a JSON file with a list of
+/// Pokémon to run offline, otherwise `POKEAPI_BASE_URL`, `POKEAPI_TIMEOUT_MS`, `POKEAPI_RETRIES`
+/// and `POKEAPI_CACHE_TTL_SECS` configure the HTTP client and its cache.
+pub fn from_env() -> Arc<dyn PokemonClient> {
+    if let Ok(path) = env::var("POKEAPI_FIXTURES") {
+        match FixturePokemonClient::from_file(&path) {
+            Ok(client) => return Arc::new(client),
+            Err(e) => warn!("Could not load Pokémon fixtures from {}: {}", path, e),
+        }
+    }
+    let number = |name: &str, default: u64| env::var(name).ok().and_then(|value| value.parse().ok()).unwrap_or(default);
+    let base_url = env::var("POKEAPI_BASE_URL").unwrap_or("https://pokeapi.co".to_string());
+    let client = HttpPokemonClient::new(&base_url, Duration::from_millis(number("POKEAPI_TIMEOUT_MS", 5000)), number("POKEAPI_RETRIES", 2) as u32);
+    Arc::new(CachedPokemonClient::new(Arc::new(client), Duration::from_secs(number("POKEAPI_CACHE_TTL_SECS", 3600))))
+}
+
+const MAX_RETRIES: u32 = 10;
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+pub struct HttpPokemonClient {
+    base_url: String,
+    client: Client,
+    retries: u32,
+    backoff: Duration,
+}
+
+impl HttpPokemonClient {
+    pub fn new(base_url: &str, timeout: Duration, retries: u32) -> HttpPokemonClient {
+        if retries > MAX_RETRIES {
+            warn!("Limiting PokeAPI retries from {} to {}.", retries, MAX_RETRIES);
+        }
+        HttpPokemonClient {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            client: Client::builder().timeout(timeout).build().expect("Could not build HTTP client"),
+            retries: retries.min(MAX_RETRIES),
+            backoff: Duration::from_millis(100),
+        }
+    }
+
+    async fn fetch(&self, id: &str) -> Result<Pokemon, PokemonError> {
+        let id = normalize_id(id)?;
+        let uri = format!("{}/api/v2/pokemon/{}", self.base_url, id);
+        let mut attempt = 0;
+        loop {
+            info!("Calling {} (attempt {})", uri, attempt + 1);
+            let error = match self.client.get(&uri).send().await {
+                Ok(response) if response.status() == reqwest::StatusCode::NOT_FOUND => return Err(PokemonError::NotFound),
+                Ok(response) if response.status().is_success() => {
+                    return response.json::<Pokemon>().await.map_err(|e| PokemonError::Upstream(e.to_string()));
+                }
+                Ok(response) if response.status().is_server_error() || response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS => {
+                    format!("upstream responded with {}", response.status())
+                }
+                Ok(response) => return Err(PokemonError::Upstream(format!("upstream responded with {}", response.status()))),
+                Err(e) => e.to_string(),
+            };
+            if attempt >= self.retries {
+                warn!("Giving up on {}: {}", uri, error);
+                return Err(PokemonError::Upstream(error));
+            }
+            tokio::time::sleep(self.delay(attempt)).await;
+            attempt += 1;
+        }
+    }
+
+    /// Doubles the backoff with every attempt, up to `MAX_BACKOFF`.
+    fn delay(&self, attempt: u32) -> Duration {
+        self.backoff.saturating_mul(2u32.saturating_pow(attempt)).min(MAX_BACKOFF)
+    }
+}
+
+impl PokemonClient for HttpPokemonClient {
+    fn get_pokemon<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Pokemon, PokemonError>> {
+        self.fetch(id).boxed()
+    }
+}
+
+pub struct CachedPokemonClient {
+    inner: Arc<dyn PokemonClient>,
+    ttl: Duration,
+    cache: Mutex<HashMap<String, (Instant, Pokemon)>>,
+}
+
+impl CachedPokemonClient {
+    pub fn new(inner: Arc<dyn PokemonClient>, ttl: Duration) -> CachedPokemonClient {
+        CachedPokemonClient { inner, ttl, cache: Mutex::new(HashMap::new()) }
+    }
+
+    fn cached(&self, id: &str) -> Option<Pokemon> {
+        let mut cache = self.cache.lock().ok()?;
+        match cache.get(id) {
+            Some((stored, pokemon)) if stored.elapsed() < self.ttl => Some(pokemon.clone()),
+            Some(_) => {
+                cache.remove(id);
+                None
+            }
+            None => None,
+        }
+    }
+}
+
+impl PokemonClient for CachedPokemonClient {
+    fn get_pokemon<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Pokemon, PokemonError>> {
+        async move {
+            let id = normalize_id(id)?;
+            if let Some(pokemon) = self.cached(&id) {
+                info!("Cache hit for Pokémon {}", id);
+                return Ok(pokemon);
+            }
+            let pokemon = self.inner.get_pokemon(&id).await?;
+            if let Ok(mut cache) = self.cache.lock() {
+                let now = Instant::now();
+                cache.insert(pokemon.id.to_string(), (now, pokemon.clone()));
+                cache.insert(pokemon.name.to_lowercase(), (now, pokemon.clone()));
+            }
+            Ok(pokemon)
+        }.boxed()
+    }
+}
+
+pub struct FixturePokemonClient {
+    pokemon: HashMap<String, Pokemon>,
+}
+
+impl FixturePokemonClient {
+    pub fn new(pokemon: Vec<Pokemon>) -> FixturePokemonClient {
+        let pokemon = pokemon.into_iter()
+            .flat_map(|pokemon| [(pokemon.id.to_string(), pokemon.clone()), (pokemon.name.to_lowercase(), pokemon)])
+            .collect();
+        FixturePokemonClient { pokemon }
+    }
+
+    pub fn from_file(path: &str) -> Result<FixturePokemonClient, String> {
+        let content = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let pokemon: Vec<Pokemon> = serde_json::from_str(&content).map_err(|e| e.to_string())?;
+        info!("Loaded {} Pokémon fixtures from {}", pokemon.len(), path);
+        Ok(FixturePokemonClient::new(pokemon))
+    }
+}
+
+impl PokemonClient for FixturePokemonClient {
+    fn get_pokemon<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Pokemon, PokemonError>> {
+        let pokemon = normalize_id(id).and_then(|id| self.pokemon.get(&id).cloned().ok_or(PokemonError::NotFound));
+        async move { pokemon }.boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+    use wiremock::matchers::{method, path};
+    use super::{CachedPokemonClient, FixturePokemonClient, HttpPokemonClient, Pokemon, PokemonClient, PokemonError};
+
+    fn bulbasaur() -> Pokemon {
+        Pokemon { name: "bulbasaur".to_string(), id: 1, weight: 69, height: 7 }
+    }
+
+    #[tokio::test]
+    async fn test_http_client_errors() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v2/pokemon/2"))
+            .respond_with(ResponseTemplate::new(503))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+        let client = HttpPokemonClient::new(&mock_server.uri(), Duration::from_secs(1), 2);
+        assert_eq!(client.get_pokemon("1").await, Err(PokemonError::NotFound));
+        assert!(matches!(client.get_pokemon("2").await, Err(PokemonError::Upstream(_))));
+    }
+
+    #[test]
+    fn test_http_client_backoff() {
+        let client = HttpPokemonClient::new("http://localhost", Duration::from_secs(1), u32::MAX);
+        assert_eq!(client.retries, super::MAX_RETRIES);
+        assert_eq!(client.delay(0), Duration::from_millis(100));
+        assert_eq!(client.delay(3), Duration::from_millis(800));
+        assert_eq!(client.delay(40), super::MAX_BACKOFF);
+        assert_eq!(client.delay(u32::MAX), super::MAX_BACKOFF);
+    }
+
+    #[tokio::test]
+    async fn test_cached_client() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v2/pokemon/1"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(bulbasaur()))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let http = HttpPokemonClient::new(&format!("{}/", mock_server.uri()), Duration::from_secs(1), 0);
+        let client = CachedPokemonClient::new(Arc::new(http), Duration::from_secs(60));
+        assert_eq!(client.get_pokemon("1").await, Ok(bulbasaur()));
+        assert_eq!(client.get_pokemon("Bulbasaur").await, Ok(bulbasaur()));
+    }
+
+    #[tokio::test]
+    async fn test_fixture_client() {
+        let client = FixturePokemonClient::from_file("fixtures/pokemon.json").unwrap();
+        assert_eq!(client.get_pokemon("1").await, Ok(bulbasaur()));
+        assert_eq!(client.get_pokemon("pikachu").await.map(|pokemon| pokemon.id), Ok(25));
+        assert_eq!(client.get_pokemon("100000").await, Err(PokemonError::NotFound));
+        assert_eq!(client.get_pokemon("../1").await, Err(PokemonError::InvalidId));
+    }
+}
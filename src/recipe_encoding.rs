@@ -0,0 +1,116 @@
+use std::io::Read;
+use base64::alphabet;
+use base64::Engine;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use base64::engine::general_purpose::STANDARD;
+use flate2::read::GzDecoder;
+use percent_encoding::percent_decode_str;
+use serde::Deserialize;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+/// Largest decompressed recipe, so a small gzip payload can not expand into gigabytes.
+const MAX_RECIPE_BYTES: u64 = 1024 * 1024;
+
+const FORGIVING: GeneralPurposeConfig = GeneralPurposeConfig::new()
+    .with_decode_padding_mode(DecodePaddingMode::Indifferent)
+    .with_decode_allow_trailing_bits(true);
+const STANDARD_FORGIVING: GeneralPurpose = GeneralPurpose::new(&alphabet::STANDARD, FORGIVING);
+const URL_SAFE_FORGIVING: GeneralPurpose = GeneralPurpose::new(&alphabet::URL_SAFE, FORGIVING);
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    /// Detects percent-encoding, either base64 alphabet, missing padding and gzip compression.
+    #[default]
+    Auto,
+    /// Standard base64 with padding.
+    Base64,
+    /// URL-safe base64, padding optional.
+    Base64url,
+    /// Gzip-compressed payload in either base64 alphabet.
+    Gzip,
+}
+
+/// Decodes an encoded recipe and checks that the result is JSON.
+pub fn decode(value: &str, encoding: Encoding) -> Result<String, String> {
+    let value = percent_decode_str(value.trim()).decode_utf8()
+        .map_err(|_| "recipe is not valid percent-encoded UTF-8".to_string())?;
+    let bytes = match encoding {
+        Encoding::Base64 => STANDARD.decode(value.as_bytes()).map_err(|e| format!("recipe is not valid base64: {}", e))?,
+        Encoding::Base64url => URL_SAFE_FORGIVING.decode(value.as_bytes()).map_err(|e| format!("recipe is not valid URL-safe base64: {}", e))?,
+        Encoding::Auto | Encoding::Gzip => decode_any_base64(&value)?,
+    };
+    let bytes = match encoding {
+        Encoding::Gzip => gunzip(&bytes)?,
+        Encoding::Auto if bytes.starts_with(&GZIP_MAGIC) => gunzip(&bytes)?,
+        _ => bytes,
+    };
+    let decoded = String::from_utf8(bytes).map_err(|_| "decoded recipe is not valid UTF-8".to_string())?;
+    serde_json::from_str::<serde_json::Value>(&decoded)
+        .map_err(|e| format!("decoded recipe is not valid JSON: {}", e))?;
+    Ok(decoded)
+}
+
+fn decode_any_base64(value: &str) -> Result<Vec<u8>, String> {
+    let value: String = value.chars().filter(|c| !c.is_whitespace()).collect();
+    if value.contains(['-', '_']) {
+        URL_SAFE_FORGIVING.decode(value.as_bytes())
+    } else {
+        STANDARD_FORGIVING.decode(value.as_bytes())
+    }.map_err(|e| format!("recipe is not valid base64: {}", e))
+}
+
+fn gunzip(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let mut decompressed = Vec::new();
+    GzDecoder::new(bytes).take(MAX_RECIPE_BYTES + 1).read_to_end(&mut decompressed)
+        .map_err(|e| format!("recipe is not valid gzip: {}", e))?;
+    if decompressed.len() as u64 > MAX_RECIPE_BYTES {
+        return Err(format!("decompressed recipe is larger than {} bytes", MAX_RECIPE_BYTES));
+    }
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use base64::Engine;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use super::{decode, Encoding, MAX_RECIPE_BYTES};
+
+    const RECIPE: &str = "{\"flour\":100,\"chocolate chips\":20}";
+
+    #[test]
+    fn test_decode_variants() {
+        assert_eq!(decode("eyJmbG91ciI6MTAwLCJjaG9jb2xhdGUgY2hpcHMiOjIwfQ==", Encoding::Auto), Ok(RECIPE.to_string()));
+        assert_eq!(decode("eyJmbG91ciI6MTAwLCJjaG9jb2xhdGUgY2hpcHMiOjIwfQ", Encoding::Auto), Ok(RECIPE.to_string()));
+        assert_eq!(decode("eyJmbG91ciI6MTAwLCJjaG9jb2xhdGUgY2hpcHMiOjIwfQ%3D%3D", Encoding::Base64), Ok(RECIPE.to_string()));
+        assert_eq!(decode("eyI_IjoxfQ", Encoding::Base64url), Ok("{\"?\":1}".to_string()));
+    }
+
+    #[test]
+    fn test_decode_gzip() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(RECIPE.as_bytes()).unwrap();
+        let encoded = URL_SAFE_NO_PAD.encode(encoder.finish().unwrap());
+        assert_eq!(decode(&encoded, Encoding::Auto), Ok(RECIPE.to_string()));
+        assert_eq!(decode(&encoded, Encoding::Gzip), Ok(RECIPE.to_string()));
+        assert!(decode("eyJmbG91ciI6MTAwfQ==", Encoding::Gzip).is_err());
+    }
+
+    #[test]
+    fn test_decode_gzip_bomb() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(&vec![b' '; MAX_RECIPE_BYTES as usize + 1]).unwrap();
+        let encoded = URL_SAFE_NO_PAD.encode(encoder.finish().unwrap());
+        assert!(encoded.len() < 4096);
+        assert!(decode(&encoded, Encoding::Gzip).unwrap_err().starts_with("decompressed recipe is larger than"));
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        assert!(decode("eyJmbG91ciI6MTAwLCJjaG9jb2xhdGUgY2hpcHMiOjIwfQ", Encoding::Base64).is_err());
+        assert_eq!(decode("bm90IGpzb24=", Encoding::Auto).unwrap_err().split(':').next(), Some("decoded recipe is not valid JSON"));
+    }
+}
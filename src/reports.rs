@@ -0,0 +1,375 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::Arc;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::Json;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sqlx::{Column, Executor, PgPool, Postgres, Row, Statement, Type, TypeInfo};
+use sqlx::postgres::{PgArguments, PgRow, PgTypeInfo};
+use sqlx::query::Query as SqlQuery;
+use tracing::{error, info, warn};
+
+const REPORTS_FILE_VARIABLE: &str = "REPORTS_FILE";
+const TIMEOUT_VARIABLE: &str = "REPORT_TIMEOUT_MS";
+const DEFAULT_TIMEOUT_MS: u64 = 5000;
+/// Postgres treats a `statement_timeout` of 0 as no timeout, so zero timeouts are ignored and
+/// larger ones are limited to this maximum.
+const MAX_TIMEOUT_MS: u64 = 60_000;
+/// SQLSTATE of a statement cancelled by `statement_timeout`.
+const QUERY_CANCELED: &str = "57014";
+/// Postgres types of result columns that can be converted to JSON, others have to be cast in the
+/// query, e.g. `NUMERIC` to `FLOAT8` or `UUID` to `TEXT`.
+const SUPPORTED_TYPES: [&str; 14] = [
+    "INT2", "INT4", "INT8", "FLOAT4", "FLOAT8", "BOOL", "DATE", "TIMESTAMPTZ", "JSON", "JSONB", "TEXT", "VARCHAR", "BPCHAR", "NAME",
+];
+
+#[derive(Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ParamType {
+    Int,
+    Float,
+    Text,
+    Bool,
+    Date,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct ReportParam {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub param_type: ParamType,
+    /// Used when the parameter is missing, a parameter without default is required.
+    pub default: Option<String>,
+}
+
+/// A read-only query, its parameters are bound to `$1`, `$2`, ... in the order they are declared.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Report {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub sql: String,
+    #[serde(default)]
+    pub params: Vec<ReportParam>,
+    pub timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Param {
+    Int(i64),
+    Float(f64),
+    Text(String),
+    Bool(bool),
+    Date(NaiveDate),
+}
+
+impl Report {
+    fn bind(&self, values: &HashMap<String, String>) -> Result<Vec<Param>, (StatusCode, String)> {
+        self.params.iter().map(|param| {
+            let value = values.get(&param.name).or(param.default.as_ref())
+                .ok_or((StatusCode::BAD_REQUEST, format!("missing parameter '{}'", param.name)))?;
+            let invalid = || (StatusCode::BAD_REQUEST, format!("parameter '{}' must be of type {:?}", param.name, param.param_type).to_lowercase());
+            Ok(match param.param_type {
+                ParamType::Int => Param::Int(value.parse().map_err(|_| invalid())?),
+                ParamType::Float => Param::Float(value.parse().map_err(|_| invalid())?),
+                ParamType::Text => Param::Text(value.clone()),
+                ParamType::Bool => Param::Bool(value.parse().map_err(|_| invalid())?),
+                ParamType::Date => Param::Date(NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| invalid())?),
+            })
+        }).collect()
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct ReportSummary {
+    name: String,
+    description: String,
+    params: Vec<String>,
+}
+
+#[derive(Clone)]
+struct ReportsState {
+    db_pool: Option<PgPool>,
+    reports: Arc<HashMap<String, Report>>,
+    timeout_ms: u64,
+}
+
+/// Loads the reports from the JSON file in `REPORTS_FILE`, `reports.json` by default.
+pub fn router(pool: Option<PgPool>) -> axum::Router {
+    info!("Initializing state.");
+    let path = env::var(REPORTS_FILE_VARIABLE).unwrap_or("reports.json".to_string());
+    let reports = match load_reports(&path) {
+        Ok(reports) => reports,
+        Err(e) => {
+            warn!("Could not load reports from {}: {}", path, e);
+            Vec::new()
+        }
+    };
+    info!("Loaded {} reports.", reports.len());
+    let shared_state = ReportsState {
+        db_pool: pool,
+        reports: Arc::new(reports.into_iter().map(|report| (report.name.clone(), report)).collect()),
+        timeout_ms: env::var(TIMEOUT_VARIABLE).ok()
+            .and_then(|value| value.parse().ok())
+            .and_then(|timeout_ms| valid_timeout(TIMEOUT_VARIABLE, timeout_ms))
+            .unwrap_or(DEFAULT_TIMEOUT_MS),
+    };
+
+    axum::Router::new()
+        .route("/", get(list_reports))
+        .route("/:name", get(run_report))
+        .with_state(shared_state)
+}
+
+fn load_reports(path: &str) -> Result<Vec<Report>, String> {
+    let content = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    parse_reports(&content)
+}
+
+fn parse_reports(content: &str) -> Result<Vec<Report>, String> {
+    let mut reports: Vec<Report> = serde_json::from_str(content).map_err(|e| e.to_string())?;
+    for report in reports.iter_mut() {
+        report.timeout_ms = report.timeout_ms.and_then(|timeout_ms| valid_timeout(&format!("timeout_ms of report '{}'", report.name), timeout_ms));
+    }
+    Ok(reports)
+}
+
+fn valid_timeout(name: &str, timeout_ms: u64) -> Option<u64> {
+    if timeout_ms == 0 {
+        warn!("Ignoring {} of 0, which would disable the timeout.", name);
+        return None;
+    }
+    if timeout_ms > MAX_TIMEOUT_MS {
+        warn!("Limiting {} from {} to {} ms.", name, timeout_ms, MAX_TIMEOUT_MS);
+    }
+    Some(timeout_ms.min(MAX_TIMEOUT_MS))
+}
+
+async fn list_reports(State(state): State<ReportsState>) -> Json<Vec<ReportSummary>> {
+    let mut reports: Vec<ReportSummary> = state.reports.values()
+        .map(|report| ReportSummary {
+            name: report.name.clone(),
+            description: report.description.clone(),
+            params: report.params.iter().map(|param| param.name.clone()).collect(),
+        })
+        .collect();
+    reports.sort_by(|a, b| a.name.cmp(&b.name));
+    Json(reports)
+}
+
+/// Runs a report with the query parameters as its parameters, `format=csv` returns CSV instead of
+/// JSON.
+async fn run_report(State(state): State<ReportsState>, Path(name): Path<String>, Query(mut values): Query<HashMap<String, String>>) -> Result<Response, (StatusCode, String)> {
+    let report = state.reports.get(&name).ok_or((StatusCode::NOT_FOUND, format!("unknown report '{}'", name)))?;
+    let csv = match values.remove("format").as_deref() {
+        None | Some("json") => false,
+        Some("csv") => true,
+        Some(format) => return Err((StatusCode::BAD_REQUEST, format!("unknown format '{}'", format))),
+    };
+    let params = report.bind(&values)?;
+    let pool = state.db_pool.as_ref().ok_or((StatusCode::SERVICE_UNAVAILABLE, "no database available".to_string()))?;
+    info!("Running report {} with {:?}", name, params);
+    let failed = |message: String| {
+        error!("Report {} failed: {}", name, message);
+        (StatusCode::INTERNAL_SERVER_ERROR, format!("report '{}' failed", name))
+    };
+    let (columns, rows) = execute(pool, report, params, report.timeout_ms.unwrap_or(state.timeout_ms)).await.map_err(|e| match e {
+        ReportError::Database(sqlx::Error::Database(e)) if e.code().as_deref() == Some(QUERY_CANCELED) => {
+            (StatusCode::GATEWAY_TIMEOUT, format!("report '{}' timed out", name))
+        }
+        ReportError::Database(e) => failed(e.to_string()),
+        ReportError::Conversion(message) => failed(message),
+    })?;
+    let rows = to_values(&columns, &rows).map_err(failed)?;
+    let columns: Vec<String> = columns.into_iter().map(|(name, _)| name).collect();
+    if csv {
+        Ok(([(header::CONTENT_TYPE, "text/csv; charset=utf-8")], to_csv(&columns, &rows)).into_response())
+    } else {
+        let objects: Vec<Map<String, Value>> = rows.into_iter()
+            .map(|row| columns.iter().cloned().zip(row).collect())
+            .collect();
+        Ok(Json(objects).into_response())
+    }
+}
+
+enum ReportError {
+    Database(sqlx::Error),
+    Conversion(String),
+}
+
+impl From<sqlx::Error> for ReportError {
+    fn from(error: sqlx::Error) -> ReportError {
+        ReportError::Database(error)
+    }
+}
+
+/// Prepares the query to learn its columns, which are also known for an empty result, and only
+/// runs it if all of them can be converted.
+async fn execute(pool: &PgPool, report: &Report, params: Vec<Param>, timeout_ms: u64) -> Result<(Vec<(String, String)>, Vec<PgRow>), ReportError> {
+    let mut transaction = pool.begin().await?;
+    sqlx::query("SET TRANSACTION READ ONLY").execute(&mut *transaction).await?;
+    sqlx::query(&format!("SET LOCAL statement_timeout = {}", timeout_ms)).execute(&mut *transaction).await?;
+    let types: Vec<PgTypeInfo> = params.iter().map(Param::type_info).collect();
+    let statement = (&mut *transaction).prepare_with(&report.sql, &types).await?;
+    let columns: Vec<(String, String)> = statement.columns().iter()
+        .map(|column| (column.name().to_string(), column.type_info().name().to_string()))
+        .collect();
+    check_columns(&columns).map_err(ReportError::Conversion)?;
+    let query = params.into_iter().fold(statement.query(), bind);
+    let rows = query.fetch_all(&mut *transaction).await?;
+    transaction.rollback().await?;
+    Ok((columns, rows))
+}
+
+fn check_columns(columns: &[(String, String)]) -> Result<(), String> {
+    match columns.iter().find(|(_, type_name)| !SUPPORTED_TYPES.contains(&type_name.as_str())) {
+        Some((name, type_name)) => Err(format!("column '{}' has the unsupported type {}", name, type_name)),
+        None => Ok(()),
+    }
+}
+
+impl Param {
+    fn type_info(&self) -> PgTypeInfo {
+        match self {
+            Param::Int(_) => <i64 as Type<Postgres>>::type_info(),
+            Param::Float(_) => <f64 as Type<Postgres>>::type_info(),
+            Param::Text(_) => <String as Type<Postgres>>::type_info(),
+            Param::Bool(_) => <bool as Type<Postgres>>::type_info(),
+            Param::Date(_) => <NaiveDate as Type<Postgres>>::type_info(),
+        }
+    }
+}
+
+fn bind(query: SqlQuery<'_, Postgres, PgArguments>, param: Param) -> SqlQuery<'_, Postgres, PgArguments> {
+    match param {
+        Param::Int(value) => query.bind(value),
+        Param::Float(value) => query.bind(value),
+        Param::Text(value) => query.bind(value),
+        Param::Bool(value) => query.bind(value),
+        Param::Date(value) => query.bind(value),
+    }
+}
+
+/// Converts rows to JSON values by the Postgres type of each column.
+fn to_values(columns: &[(String, String)], rows: &[PgRow]) -> Result<Vec<Vec<Value>>, String> {
+    rows.iter().map(|row| {
+        columns.iter().enumerate().map(|(index, (name, type_name))| {
+            let value = match type_name.as_str() {
+                "INT2" => row.try_get::<Option<i16>, _>(index).map(|value| value.map(Value::from)),
+                "INT4" => row.try_get::<Option<i32>, _>(index).map(|value| value.map(Value::from)),
+                "INT8" => row.try_get::<Option<i64>, _>(index).map(|value| value.map(Value::from)),
+                "FLOAT4" => row.try_get::<Option<f32>, _>(index).map(|value| value.map(Value::from)),
+                "FLOAT8" => row.try_get::<Option<f64>, _>(index).map(|value| value.map(Value::from)),
+                "BOOL" => row.try_get::<Option<bool>, _>(index).map(|value| value.map(Value::from)),
+                "DATE" => row.try_get::<Option<NaiveDate>, _>(index).map(|value| value.map(|date| Value::from(date.to_string()))),
+                "TIMESTAMPTZ" => row.try_get::<Option<chrono::DateTime<chrono::Utc>>, _>(index).map(|value| value.map(|time| Value::from(time.to_rfc3339()))),
+                "JSON" | "JSONB" => row.try_get::<Option<Value>, _>(index),
+                "TEXT" | "VARCHAR" | "BPCHAR" | "NAME" => row.try_get::<Option<String>, _>(index).map(|value| value.map(Value::from)),
+                _ => return Err(format!("column '{}' has the unsupported type {}", name, type_name)),
+            };
+            value.map(|value| value.unwrap_or(Value::Null))
+                .map_err(|e| format!("could not read column '{}' of type {}: {}", name, type_name, e))
+        }).collect()
+    }).collect()
+}
+
+fn to_csv(columns: &[String], rows: &[Vec<Value>]) -> String {
+    let field = |value: &str| {
+        if value.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value.to_string()
+        }
+    };
+    let mut csv = String::new();
+    let mut line = |values: Vec<String>| {
+        csv.push_str(&values.join(","));
+        csv.push_str("\r\n");
+    };
+    line(columns.iter().map(|column| field(column)).collect());
+    for row in rows {
+        line(row.iter().map(|value| match value {
+            Value::Null => String::new(),
+            Value::String(text) => field(text),
+            value => field(&value.to_string()),
+        }).collect());
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use chrono::NaiveDate;
+    use serde_json::{json, Value};
+    use sqlx::PgPool;
+    use super::{check_columns, execute, load_reports, parse_reports, to_csv, Param, Report, ReportError, MAX_TIMEOUT_MS};
+
+    #[test]
+    fn test_load_and_bind() {
+        let reports = load_reports("reports.json").unwrap();
+        let report = reports.iter().find(|report| report.name == "packets_since").unwrap();
+        let values: HashMap<String, String> = [("since".to_string(), "2023-12-01".to_string())].into();
+        assert_eq!(report.bind(&values), Ok(vec![Param::Date(NaiveDate::from_ymd_opt(2023, 12, 1).unwrap()), Param::Int(10)]));
+        let values: HashMap<String, String> = [("since".to_string(), "yesterday".to_string())].into();
+        assert_eq!(report.bind(&values).unwrap_err().1, "parameter 'since' must be of type date");
+        assert!(report.bind(&HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn test_timeouts_are_limited() {
+        let reports = parse_reports(r#"[
+            {"name": "unlimited", "sql": "SELECT 1", "timeout_ms": 0},
+            {"name": "forever", "sql": "SELECT 1", "timeout_ms": 18446744073709551615},
+            {"name": "quick", "sql": "SELECT 1", "timeout_ms": 100},
+            {"name": "default", "sql": "SELECT 1"}
+        ]"#).unwrap();
+        let timeouts: Vec<Option<u64>> = reports.iter().map(|report| report.timeout_ms).collect();
+        assert_eq!(timeouts, vec![None, Some(MAX_TIMEOUT_MS), Some(100), None]);
+    }
+
+    #[test]
+    fn test_to_csv() {
+        let columns = vec!["gift_name".to_string(), "total".to_string()];
+        let rows = vec![vec![json!("Toy, \"deluxe\""), json!(5)], vec![json!("Coal"), Value::Null]];
+        assert_eq!(to_csv(&columns, &rows), "gift_name,total\r\n\"Toy, \"\"deluxe\"\"\",5\r\nCoal,\r\n");
+        assert_eq!(to_csv(&columns, &[]), "gift_name,total\r\n");
+    }
+
+    #[test]
+    fn test_check_columns() {
+        let column = |name: &str, type_name: &str| (name.to_string(), type_name.to_string());
+        assert!(check_columns(&[column("total", "INT8"), column("name", "VARCHAR")]).is_ok());
+        assert_eq!(check_columns(&[column("total", "INT8"), column("amount", "NUMERIC")]),
+            Err("column 'amount' has the unsupported type NUMERIC".to_string()));
+        assert!(check_columns(&[column("tags", "TEXT[]")]).is_err());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn test_execute() {
+        let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL points to a Postgres database");
+        let pool = PgPool::connect(&url).await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+        let reports = load_reports("reports.json").unwrap();
+        let report = reports.iter().find(|report| report.name == "packets_since").unwrap();
+        let params = report.bind(&[("since".to_string(), "2999-01-01".to_string())].into()).unwrap();
+        let Ok((columns, rows)) = execute(&pool, report, params, 1000).await else {
+            panic!("packets_since failed");
+        };
+        let names: Vec<&str> = columns.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["key", "saved_at", "expires_at"]);
+        assert!(rows.is_empty());
+
+        let report = Report { name: "numeric".to_string(), description: String::new(), sql: "SELECT 1.5::NUMERIC AS amount".to_string(), params: vec![], timeout_ms: None };
+        match execute(&pool, &report, vec![], 1000).await {
+            Err(ReportError::Conversion(message)) => assert_eq!(message, "column 'amount' has the unsupported type NUMERIC"),
+            _ => panic!("NUMERIC columns are not supported"),
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use std::env;
+use axum::http::StatusCode;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Keys are configured as `id:secret` pairs separated by commas. The first key signs new cookies,
+/// all keys are accepted when verifying so that old cookies stay valid while keys are rotated.
+const KEYS_VARIABLE: &str = "RECIPE_COOKIE_KEYS";
+/// Unsigned cookies are rejected unless this is `false`, which is only meant for migrating clients
+/// that still send cookies issued before signing was introduced.
+const REQUIRE_SIGNED_VARIABLE: &str = "RECIPE_COOKIE_REQUIRE_SIGNED";
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum CookieError {
+    Malformed,
+    Unsigned,
+    UnknownKey,
+    InvalidSignature,
+}
+
+impl From<CookieError> for (StatusCode, String) {
+    fn from(error: CookieError) -> (StatusCode, String) {
+        match error {
+            CookieError::Malformed => (StatusCode::BAD_REQUEST, "malformed cookie".to_string()),
+            CookieError::Unsigned => (StatusCode::UNAUTHORIZED, "cookie is not signed".to_string()),
+            CookieError::UnknownKey => (StatusCode::FORBIDDEN, "cookie is signed with an unknown key".to_string()),
+            CookieError::InvalidSignature => (StatusCode::FORBIDDEN, "cookie signature is invalid".to_string()),
+        }
+    }
+}
+
+pub struct CookieSigner {
+    keys: Vec<(String, Vec<u8>)>,
+    require_signed: bool,
+}
+
+impl CookieSigner {
+    pub fn new(keys: Vec<(String, Vec<u8>)>, require_signed: bool) -> CookieSigner {
+        CookieSigner { keys, require_signed }
+    }
+
+    pub fn from_env() -> CookieSigner {
+        let keys: Vec<(String, Vec<u8>)> = env::var(KEYS_VARIABLE).unwrap_or_default()
+            .split(',')
+            .filter_map(|key| key.split_once(':'))
+            .map(|(id, secret)| (id.trim().to_string(), secret.trim().as_bytes().to_vec()))
+            .filter(|(id, secret)| !id.is_empty() && !secret.is_empty())
+            .collect();
+        let require_signed = env::var(REQUIRE_SIGNED_VARIABLE).map(|value| value != "false").unwrap_or(true);
+        if !require_signed {
+            warn!("Accepting unsigned recipe cookies.");
+        }
+        if keys.is_empty() {
+            warn!("No recipe cookie keys configured, signing with an ephemeral key.");
+            let secret = Uuid::new_v4().as_bytes().to_vec();
+            return CookieSigner::new(vec![("ephemeral".to_string(), secret)], require_signed);
+        }
+        info!("Loaded {} recipe cookie keys.", keys.len());
+        CookieSigner::new(keys, require_signed)
+    }
+
+    /// Appends the id of the signing key and the signature to `value` as `value.id.signature`.
+    pub fn sign(&self, value: &str) -> String {
+        let (id, secret) = &self.keys[0];
+        let signature = mac(secret, id, value).finalize().into_bytes();
+        format!("{}.{}.{}", value, id, URL_SAFE_NO_PAD.encode(signature))
+    }
+
+    /// Returns the value of a signed cookie, or of an unsigned cookie if those are still accepted.
+    pub fn verify<'a>(&self, cookie: &'a str) -> Result<&'a str, CookieError> {
+        let mut parts = cookie.rsplitn(3, '.');
+        let (signature_part, id, value) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(signature_part), Some(id), Some(value)) => (signature_part, id, value),
+            _ if self.require_signed => return Err(CookieError::Unsigned),
+            _ => return Ok(cookie),
+        };
+        let (_, secret) = self.keys.iter().find(|(key_id, _)| key_id == id).ok_or(CookieError::UnknownKey)?;
+        let expected = URL_SAFE_NO_PAD.decode(signature_part).map_err(|_| CookieError::Malformed)?;
+        mac(secret, id, value).verify_slice(&expected).map_err(|_| CookieError::InvalidSignature)?;
+        Ok(value)
+    }
+}
+
+fn mac(secret: &[u8], id: &str, value: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(id.as_bytes());
+    mac.update(b".");
+    mac.update(value.as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CookieError, CookieSigner};
+
+    fn key(id: &str, secret: &str) -> (String, Vec<u8>) {
+        (id.to_string(), secret.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let signer = CookieSigner::new(vec![key("2024", "new secret")], true);
+        let cookie = signer.sign("eyJmbG91ciI6MTAwfQ==");
+        assert!(cookie.starts_with("eyJmbG91ciI6MTAwfQ==.2024."));
+        assert_eq!(signer.verify(&cookie), Ok("eyJmbG91ciI6MTAwfQ=="));
+    }
+
+    #[test]
+    fn test_verify_rejects_tampering() {
+        let signer = CookieSigner::new(vec![key("2024", "new secret")], true);
+        let cookie = signer.sign("eyJmbG91ciI6MTAwfQ==").replacen("eyJmbG91ciI6MTAw", "eyJmbG91ciI6OTk5", 1);
+        assert_eq!(signer.verify(&cookie), Err(CookieError::InvalidSignature));
+        assert_eq!(signer.verify("eyJmbG91ciI6MTAwfQ==.2022.c2ln"), Err(CookieError::UnknownKey));
+        assert_eq!(signer.verify("eyJmbG91ciI6MTAwfQ=="), Err(CookieError::Unsigned));
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let old = CookieSigner::new(vec![key("2023", "old secret")], false);
+        let rotated = CookieSigner::new(vec![key("2024", "new secret"), key("2023", "old secret")], false);
+        let cookie = old.sign("e30=");
+        assert_eq!(rotated.verify(&cookie), Ok("e30="));
+        assert!(rotated.sign("e30=").contains(".2024."));
+        assert_eq!(rotated.verify("e30="), Ok("e30="));
+    }
+}
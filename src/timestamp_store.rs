@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use axum::http::StatusCode;
+use chrono::{DateTime, Duration, Utc};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use tracing::error;
+
+#[derive(Debug, PartialEq)]
+pub struct StoreError(pub String);
+
+impl From<StoreError> for StatusCode {
+    fn from(error: StoreError) -> StatusCode {
+        error!("Timestamp store failed: {}", error.0);
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+}
+
+impl From<sqlx::Error> for StoreError {
+    fn from(error: sqlx::Error) -> StoreError {
+        StoreError(error.to_string())
+    }
+}
+
+fn expires_at(now: DateTime<Utc>, ttl: Option<Duration>) -> Result<Option<DateTime<Utc>>, StoreError> {
+    ttl.map(|ttl| now.checked_add_signed(ttl).ok_or(StoreError(format!("ttl {} is out of range", ttl))))
+        .transpose()
+}
+
+#[derive(Serialize, FromRow, Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub key: String,
+    pub saved_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl Entry {
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// Stores when packets were saved. Expired entries are never returned, even before the sweeper
+/// removes them.
+pub trait TimestampStore: Send + Sync {
+    fn save<'a>(&'a self, key: &'a str, now: DateTime<Utc>, ttl: Option<Duration>) -> BoxFuture<'a, Result<(), StoreError>>;
+    fn load<'a>(&'a self, key: &'a str, now: DateTime<Utc>) -> BoxFuture<'a, Result<Option<Entry>, StoreError>>;
+    fn list(&self, now: DateTime<Utc>) -> BoxFuture<'_, Result<Vec<Entry>, StoreError>>;
+    /// Returns whether there was an entry to delete.
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<bool, StoreError>>;
+    /// Removes expired entries and returns how many there were.
+    fn sweep(&self, now: DateTime<Utc>) -> BoxFuture<'_, Result<u64, StoreError>>;
+}
+
+#[derive(Default)]
+pub struct MemoryTimestampStore {
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl MemoryTimestampStore {
+    fn entries(&self) -> Result<std::sync::MutexGuard<'_, HashMap<String, Entry>>, StoreError> {
+        self.entries.lock().map_err(|e| StoreError(e.to_string()))
+    }
+}
+
+impl TimestampStore for MemoryTimestampStore {
+    fn save<'a>(&'a self, key: &'a str, now: DateTime<Utc>, ttl: Option<Duration>) -> BoxFuture<'a, Result<(), StoreError>> {
+        let result = expires_at(now, ttl).and_then(|expires_at| self.entries().map(|mut entries| {
+            entries.insert(key.to_string(), Entry { key: key.to_string(), saved_at: now, expires_at });
+        }));
+        async move { result }.boxed()
+    }
+
+    fn load<'a>(&'a self, key: &'a str, now: DateTime<Utc>) -> BoxFuture<'a, Result<Option<Entry>, StoreError>> {
+        let result = self.entries().map(|entries| entries.get(key).filter(|entry| !entry.is_expired(now)).cloned());
+        async move { result }.boxed()
+    }
+
+    fn list(&self, now: DateTime<Utc>) -> BoxFuture<'_, Result<Vec<Entry>, StoreError>> {
+        let result = self.entries().map(|entries| {
+            let mut entries: Vec<Entry> = entries.values().filter(|entry| !entry.is_expired(now)).cloned().collect();
+            entries.sort_by(|a, b| a.key.cmp(&b.key));
+            entries
+        });
+        async move { result }.boxed()
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<bool, StoreError>> {
+        let result = self.entries().map(|mut entries| entries.remove(key).is_some());
+        async move { result }.boxed()
+    }
+
+    fn sweep(&self, now: DateTime<Utc>) -> BoxFuture<'_, Result<u64, StoreError>> {
+        let result = self.entries().map(|mut entries| {
+            let before = entries.len();
+            entries.retain(|_, entry| !entry.is_expired(now));
+            (before - entries.len()) as u64
+        });
+        async move { result }.boxed()
+    }
+}
+
+/// Keeps the timestamps in the `packet_timestamps` table, so that they survive restarts and are
+/// shared between instances.
+pub struct PgTimestampStore {
+    pool: PgPool,
+}
+
+impl PgTimestampStore {
+    pub fn new(pool: PgPool) -> PgTimestampStore {
+        PgTimestampStore { pool }
+    }
+}
+
+impl TimestampStore for PgTimestampStore {
+    fn save<'a>(&'a self, key: &'a str, now: DateTime<Utc>, ttl: Option<Duration>) -> BoxFuture<'a, Result<(), StoreError>> {
+        async move {
+            let expires_at = expires_at(now, ttl)?;
+            sqlx::query("INSERT INTO packet_timestamps (key, saved_at, expires_at) VALUES ($1, $2, $3)
+                    ON CONFLICT (key) DO UPDATE SET saved_at = EXCLUDED.saved_at, expires_at = EXCLUDED.expires_at")
+                .bind(key)
+                .bind(now)
+                .bind(expires_at)
+                .execute(&self.pool)
+                .await?;
+            Ok(())
+        }.boxed()
+    }
+
+    fn load<'a>(&'a self, key: &'a str, now: DateTime<Utc>) -> BoxFuture<'a, Result<Option<Entry>, StoreError>> {
+        async move {
+            Ok(sqlx::query_as::<_, Entry>("SELECT key, saved_at, expires_at FROM packet_timestamps
+                    WHERE key = $1 AND (expires_at IS NULL OR expires_at > $2)")
+                .bind(key)
+                .bind(now)
+                .fetch_optional(&self.pool)
+                .await?)
+        }.boxed()
+    }
+
+    fn list(&self, now: DateTime<Utc>) -> BoxFuture<'_, Result<Vec<Entry>, StoreError>> {
+        async move {
+            Ok(sqlx::query_as::<_, Entry>("SELECT key, saved_at, expires_at FROM packet_timestamps
+                    WHERE expires_at IS NULL OR expires_at > $1 ORDER BY key")
+                .bind(now)
+                .fetch_all(&self.pool)
+                .await?)
+        }.boxed()
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<bool, StoreError>> {
+        async move {
+            let result = sqlx::query("DELETE FROM packet_timestamps WHERE key = $1")
+                .bind(key)
+                .execute(&self.pool)
+                .await?;
+            Ok(result.rows_affected() > 0)
+        }.boxed()
+    }
+
+    fn sweep(&self, now: DateTime<Utc>) -> BoxFuture<'_, Result<u64, StoreError>> {
+        async move {
+            let result = sqlx::query("DELETE FROM packet_timestamps WHERE expires_at <= $1")
+                .bind(now)
+                .execute(&self.pool)
+                .await?;
+            Ok(result.rows_affected())
+        }.boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+    use super::{MemoryTimestampStore, TimestampStore};
+
+    #[tokio::test]
+    async fn test_memory_store() {
+        let store = MemoryTimestampStore::default();
+        let now = Utc.with_ymd_and_hms(2023, 12, 12, 12, 0, 0).unwrap();
+        store.save("packet", now, None).await.unwrap();
+        store.save("expiring", now, Some(Duration::seconds(10))).await.unwrap();
+        assert_eq!(store.load("expiring", now + Duration::seconds(9)).await.unwrap().map(|entry| entry.saved_at), Some(now));
+        assert_eq!(store.load("expiring", now + Duration::seconds(10)).await.unwrap(), None);
+        assert_eq!(store.list(now + Duration::seconds(10)).await.unwrap().len(), 1);
+        assert_eq!(store.sweep(now + Duration::seconds(10)).await.unwrap(), 1);
+        assert!(store.delete("packet").await.unwrap());
+        assert!(!store.delete("packet").await.unwrap());
+        assert!(store.list(now).await.unwrap().is_empty());
+    }
+}
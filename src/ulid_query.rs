@@ -0,0 +1,397 @@
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, NaiveTime, Timelike, Utc};
+use chrono_tz::Tz;
+use ulid::Ulid;
+
+const WEEKDAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+const RANDOM_BITS: u32 = 80;
+/// Limits on predicates, so that parsing and evaluating them can not exhaust the stack.
+const MAX_PREDICATE_LENGTH: usize = 1024;
+const MAX_NESTING: usize = 64;
+
+/// A fixed UTC offset, or an IANA time zone whose offset follows daylight saving time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Zone {
+    Offset(FixedOffset),
+    Named(Tz),
+}
+
+impl Zone {
+    fn local(&self, datetime: DateTime<Utc>) -> DateTime<FixedOffset> {
+        match self {
+            Zone::Offset(offset) => datetime.with_timezone(offset),
+            Zone::Named(tz) => datetime.with_timezone(tz).fixed_offset(),
+        }
+    }
+}
+
+/// Parses an IANA name like `Europe/Berlin`, `UTC`, `Z` or an offset like `+01:00`, `-0530` or `UTC+2`.
+pub fn parse_timezone(timezone: &str) -> Result<Zone, String> {
+    if let Ok(tz) = timezone.trim().parse::<Tz>() {
+        return Ok(Zone::Named(tz));
+    }
+    parse_offset(timezone).map(Zone::Offset)
+}
+
+fn parse_offset(timezone: &str) -> Result<FixedOffset, String> {
+    let invalid = || format!("invalid time zone '{}', expected a name like Europe/Berlin, UTC or an offset like +01:00", timezone);
+    let offset = timezone.trim().trim_start_matches("UTC").trim_start_matches("utc");
+    if offset.is_empty() || offset == "Z" {
+        return Ok(FixedOffset::east_opt(0).expect("zero offset is valid"));
+    }
+    let (sign, rest) = if let Some(rest) = offset.strip_prefix('+') {
+        (1, rest)
+    } else if let Some(rest) = offset.strip_prefix('-') {
+        (-1, rest)
+    } else {
+        return Err(invalid());
+    };
+    let (hours, minutes) = match rest.split_once(':') {
+        Some((hours, minutes)) => (hours, minutes),
+        None if rest.len() == 4 && rest.is_char_boundary(2) => rest.split_at(2),
+        None => (rest, "0"),
+    };
+    let hours: i32 = hours.parse().map_err(|_| invalid())?;
+    let minutes: i32 = minutes.parse().map_err(|_| invalid())?;
+    if !(0..=23).contains(&hours) || !(0..60).contains(&minutes) {
+        return Err(invalid());
+    }
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60)).ok_or_else(invalid)
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Field {
+    Date,
+    Year,
+    Month,
+    Day,
+    Weekday,
+    Hour,
+    Time,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Field> {
+        Some(match name {
+            "date" => Field::Date,
+            "year" => Field::Year,
+            "month" => Field::Month,
+            "day" => Field::Day,
+            "weekday" => Field::Weekday,
+            "hour" => Field::Hour,
+            "time" => Field::Time,
+            _ => return None,
+        })
+    }
+
+    /// Fields that wrap around, so that `weekday in sat..mon` or `time in 22:00..06:00` make sense.
+    fn is_cyclic(&self) -> bool {
+        matches!(self, Field::Month | Field::Weekday | Field::Hour | Field::Time)
+    }
+
+    /// Every field is compared as a number: days since the common era for dates, 0 for Monday,
+    /// seconds since midnight for times.
+    fn value(&self, local: &DateTime<FixedOffset>) -> i64 {
+        match self {
+            Field::Date => local.num_days_from_ce() as i64,
+            Field::Year => local.year() as i64,
+            Field::Month => local.month() as i64,
+            Field::Day => local.day() as i64,
+            Field::Weekday => local.weekday().num_days_from_monday() as i64,
+            Field::Hour => local.hour() as i64,
+            Field::Time => local.num_seconds_from_midnight() as i64,
+        }
+    }
+
+    fn parse_value(&self, literal: &str) -> Result<i64, String> {
+        let invalid = || format!("invalid {:?} value '{}'", self, literal).to_lowercase();
+        let prefix = literal.get(..3).unwrap_or_default();
+        match self {
+            Field::Date => NaiveDate::parse_from_str(literal, "%Y-%m-%d").map(|date| date.num_days_from_ce() as i64).map_err(|_| invalid()),
+            Field::Time => NaiveTime::parse_from_str(literal, "%H:%M:%S")
+                .or_else(|_| NaiveTime::parse_from_str(literal, "%H:%M"))
+                .map(|time| time.num_seconds_from_midnight() as i64)
+                .map_err(|_| invalid()),
+            Field::Weekday => match WEEKDAYS.iter().position(|day| *day == prefix) {
+                Some(index) => Ok(index as i64),
+                None => literal.parse().ok().filter(|day| (0..7).contains(day)).ok_or_else(invalid),
+            },
+            Field::Month => match MONTHS.iter().position(|month| *month == prefix) {
+                Some(index) => Ok(index as i64 + 1),
+                None => literal.parse().ok().filter(|month| (1..=12).contains(month)).ok_or_else(invalid),
+            },
+            Field::Year | Field::Day | Field::Hour => literal.parse().map_err(|_| invalid()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Field, Comparison, i64),
+    In(Field, i64, i64),
+    /// Tests a bit of the random part, bit 0 being the least significant one.
+    Bit(u32, bool),
+    Future,
+    Past,
+}
+
+/// A named condition on the timestamp or random part of ULIDs, for example
+/// `month = dec and day = 24`, `weekday in sat..sun and not time in 09:00..17:00` or `bit 0 = 1`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Predicate {
+    expr: Expr,
+}
+
+impl Predicate {
+    pub fn parse(source: &str) -> Result<Predicate, String> {
+        if source.len() > MAX_PREDICATE_LENGTH {
+            return Err(format!("predicates may have at most {} characters", MAX_PREDICATE_LENGTH));
+        }
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, position: 0, depth: 0 };
+        let expr = parser.or()?;
+        match parser.peek() {
+            None => Ok(Predicate { expr }),
+            Some(token) => Err(format!("unexpected '{}'", token)),
+        }
+    }
+
+    pub fn matches(&self, ulid: &Ulid, timezone: &Zone, now: DateTime<Utc>) -> bool {
+        let datetime: DateTime<Utc> = ulid.datetime().into();
+        evaluate(&self.expr, ulid, &timezone.local(datetime), datetime > now)
+    }
+}
+
+fn evaluate(expr: &Expr, ulid: &Ulid, local: &DateTime<FixedOffset>, future: bool) -> bool {
+    match expr {
+        Expr::And(left, right) => evaluate(left, ulid, local, future) && evaluate(right, ulid, local, future),
+        Expr::Or(left, right) => evaluate(left, ulid, local, future) || evaluate(right, ulid, local, future),
+        Expr::Not(inner) => !evaluate(inner, ulid, local, future),
+        Expr::Compare(field, comparison, expected) => {
+            let value = field.value(local);
+            match comparison {
+                Comparison::Equal => value == *expected,
+                Comparison::NotEqual => value != *expected,
+                Comparison::Less => value < *expected,
+                Comparison::LessOrEqual => value <= *expected,
+                Comparison::Greater => value > *expected,
+                Comparison::GreaterOrEqual => value >= *expected,
+            }
+        }
+        Expr::In(field, from, to) => {
+            let value = field.value(local);
+            if from <= to || !field.is_cyclic() {
+                *from <= value && value <= *to
+            } else {
+                value >= *from || value <= *to
+            }
+        }
+        Expr::Bit(bit, set) => (ulid.random() >> bit & 1 == 1) == *set,
+        Expr::Future => future,
+        Expr::Past => !future,
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let mut end = start + c.len_utf8();
+        let mut take_while = |end: &mut usize, accept: fn(char) -> bool| {
+            while let Some((index, c)) = chars.next_if(|(_, c)| accept(*c)) {
+                *end = index + c.len_utf8();
+            }
+        };
+        match c {
+            c if c.is_whitespace() => continue,
+            '(' | ')' => {}
+            '=' => {}
+            '!' | '<' | '>' => take_while(&mut end, |c| c == '='),
+            '.' => take_while(&mut end, |c| c == '.'),
+            c if c.is_ascii_alphabetic() => take_while(&mut end, |c| c.is_ascii_alphabetic() || c == '_'),
+            c if c.is_ascii_digit() => take_while(&mut end, |c| c.is_ascii_digit() || c == '-' || c == ':'),
+            c => return Err(format!("unexpected character '{}' at position {}", c, start)),
+        }
+        tokens.push(source[start..end].to_lowercase());
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    position: usize,
+    /// Nesting of `not` and parentheses around the current position.
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.position).map(String::as_str)
+    }
+
+    fn next(&mut self) -> Result<String, String> {
+        let token = self.tokens.get(self.position).cloned().ok_or("unexpected end of expression".to_string())?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        match self.next()? {
+            token if token == expected => Ok(()),
+            token => Err(format!("expected '{}' but found '{}'", expected, token)),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.peek() == Some("or") {
+            self.position += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while self.peek() == Some("and") {
+            self.position += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Parser) -> Result<T, String>) -> Result<T, String> {
+        if self.depth >= MAX_NESTING {
+            return Err(format!("expressions may be nested at most {} levels deep", MAX_NESTING));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.next()?.as_str() {
+            "not" => self.nested(|parser| Ok(Expr::Not(Box::new(parser.unary()?)))),
+            "(" => self.nested(|parser| {
+                let expr = parser.or()?;
+                parser.expect(")")?;
+                Ok(expr)
+            }),
+            "future" => Ok(Expr::Future),
+            "past" => Ok(Expr::Past),
+            "bit" => {
+                let bit = self.next()?.parse::<u32>().ok().filter(|bit| *bit < RANDOM_BITS)
+                    .ok_or(format!("bit must be a number below {}", RANDOM_BITS))?;
+                self.expect("=")?;
+                match self.next()?.as_str() {
+                    "0" => Ok(Expr::Bit(bit, false)),
+                    "1" => Ok(Expr::Bit(bit, true)),
+                    token => Err(format!("a bit is 0 or 1, not '{}'", token)),
+                }
+            }
+            name => {
+                let field = Field::parse(name).ok_or(format!("unknown field '{}'", name))?;
+                let comparison = match self.next()?.as_str() {
+                    "in" => {
+                        let from = field.parse_value(&self.next()?)?;
+                        self.expect("..")?;
+                        let to = field.parse_value(&self.next()?)?;
+                        return Ok(Expr::In(field, from, to));
+                    }
+                    "=" | "==" => Comparison::Equal,
+                    "!=" => Comparison::NotEqual,
+                    "<" => Comparison::Less,
+                    "<=" => Comparison::LessOrEqual,
+                    ">" => Comparison::Greater,
+                    ">=" => Comparison::GreaterOrEqual,
+                    token => return Err(format!("expected a comparison after '{}' but found '{}'", name, token)),
+                };
+                Ok(Expr::Compare(field, comparison, field.parse_value(&self.next()?)?))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use chrono::{FixedOffset, TimeZone, Utc};
+    use ulid::Ulid;
+    use super::{parse_timezone, Predicate, Zone};
+
+    fn offset(seconds: i32) -> Result<Zone, String> {
+        Ok(Zone::Offset(FixedOffset::east_opt(seconds).unwrap()))
+    }
+
+    fn matches(expression: &str, ulid: &str, timezone: &str) -> bool {
+        let now = Utc.with_ymd_and_hms(2023, 12, 12, 12, 0, 0).unwrap();
+        Predicate::parse(expression).unwrap().matches(&Ulid::from_str(ulid).unwrap(), &parse_timezone(timezone).unwrap(), now)
+    }
+
+    #[test]
+    fn test_predicates() {
+        // 2023-12-24 23:30 UTC, a Sunday
+        let ulid = Ulid::from_parts(Utc.with_ymd_and_hms(2023, 12, 24, 23, 30, 0).unwrap().timestamp_millis() as u64, 0b101).to_string();
+        assert!(matches("month = dec and day = 24", &ulid, "UTC"));
+        assert!(matches("date = 2023-12-25 and weekday = monday", &ulid, "+01:00"));
+        assert!(matches("weekday in sat..sun and time in 22:00..06:00", &ulid, "UTC"));
+        assert!(!matches("time in 22:00..06:00", &ulid, "-05:00"));
+        assert!(matches("date >= 2023-12-01 and date < 2024-01-01 and future", &ulid, "UTC"));
+        assert!(matches("bit 0 = 1 and bit 1 = 0 and (bit 2 = 1 or hour = 3)", &ulid, "UTC"));
+        assert!(matches("not (year != 2023)", &ulid, "utc+2"));
+    }
+
+    #[test]
+    fn test_named_timezone() {
+        // 22:30 UTC is midnight in Berlin in summer, but not in winter.
+        let summer = Ulid::from_parts(Utc.with_ymd_and_hms(2023, 7, 1, 22, 30, 0).unwrap().timestamp_millis() as u64, 0).to_string();
+        let winter = Ulid::from_parts(Utc.with_ymd_and_hms(2023, 12, 23, 22, 30, 0).unwrap().timestamp_millis() as u64, 0).to_string();
+        assert!(matches("day = 2 and hour = 0", &summer, "Europe/Berlin"));
+        assert!(matches("day = 23 and hour = 23", &winter, "Europe/Berlin"));
+        assert!(matches("day = 23 and hour = 23", &winter, " Europe/Berlin "));
+        assert!(!matches("day = 24", &winter, "Europe/Berlin"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(Predicate::parse("colour = red"), Err("unknown field 'colour'".to_string()));
+        assert_eq!(Predicate::parse("month = 13"), Err("invalid month value '13'".to_string()));
+        assert_eq!(Predicate::parse("(day = 1"), Err("unexpected end of expression".to_string()));
+        assert_eq!(Predicate::parse("day = 1 day"), Err("unexpected 'day'".to_string()));
+        assert!(Predicate::parse("bit 80 = 1").is_err());
+        assert!(Predicate::parse("day = 1 & day = 2").is_err());
+        assert!(parse_timezone("Mars/Olympus_Mons").is_err());
+        assert_eq!(parse_timezone("-0530"), offset(-19800));
+    }
+
+    #[test]
+    fn test_parse_timezone_invalid() {
+        for timezone in ["é", "+é", "+1é0", "+999999", "-24", "+01:60", "+01:-5"] {
+            assert!(parse_timezone(timezone).is_err(), "{}", timezone);
+        }
+        assert_eq!(parse_timezone("+23:59"), offset(86340));
+    }
+
+    #[test]
+    fn test_parse_limits() {
+        assert!(Predicate::parse(&format!("{}day = 1{}", "(".repeat(64), ")".repeat(64))).is_ok());
+        assert!(Predicate::parse(&format!("{}day = 1{}", "(".repeat(65), ")".repeat(65))).is_err());
+        assert!(Predicate::parse(&format!("{}day = 1", "not ".repeat(200))).is_err());
+        assert!(Predicate::parse(&"(".repeat(2_000_000)).is_err());
+        assert!(Predicate::parse(&vec!["day = 1"; 200].join(" and ")).is_err());
+    }
+}
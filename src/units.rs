@@ -0,0 +1,140 @@
+use std::sync::OnceLock;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Dimension {
+    Count,
+    Mass,
+    Volume,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Unit {
+    pub name: &'static str,
+    pub dimension: Dimension,
+    /// Size of the unit in the base unit of its dimension, which is one piece, one gram or one millilitre.
+    pub factor: f64,
+}
+
+const UNITS: &[Unit] = &[
+    Unit { name: "", dimension: Dimension::Count, factor: 1.0 },
+    Unit { name: "pc", dimension: Dimension::Count, factor: 1.0 },
+    Unit { name: "pcs", dimension: Dimension::Count, factor: 1.0 },
+    Unit { name: "piece", dimension: Dimension::Count, factor: 1.0 },
+    Unit { name: "pieces", dimension: Dimension::Count, factor: 1.0 },
+    Unit { name: "dozen", dimension: Dimension::Count, factor: 12.0 },
+    Unit { name: "mg", dimension: Dimension::Mass, factor: 0.001 },
+    Unit { name: "g", dimension: Dimension::Mass, factor: 1.0 },
+    Unit { name: "kg", dimension: Dimension::Mass, factor: 1000.0 },
+    Unit { name: "oz", dimension: Dimension::Mass, factor: 28.349523125 },
+    Unit { name: "lb", dimension: Dimension::Mass, factor: 453.59237 },
+    Unit { name: "ml", dimension: Dimension::Volume, factor: 1.0 },
+    Unit { name: "cl", dimension: Dimension::Volume, factor: 10.0 },
+    Unit { name: "dl", dimension: Dimension::Volume, factor: 100.0 },
+    Unit { name: "l", dimension: Dimension::Volume, factor: 1000.0 },
+    Unit { name: "tsp", dimension: Dimension::Volume, factor: 4.92892159375 },
+    Unit { name: "tbsp", dimension: Dimension::Volume, factor: 14.78676478125 },
+    Unit { name: "fl oz", dimension: Dimension::Volume, factor: 29.5735295625 },
+    Unit { name: "cup", dimension: Dimension::Volume, factor: 236.5882365 },
+];
+
+fn amount_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| Regex::new(r"^\s*(-?[0-9]*\.?[0-9]+)\s*([a-zA-Z ]*?)\s*$").expect("Amount pattern is valid"))
+}
+
+pub fn unit(name: &str) -> Result<&'static Unit, String> {
+    let name = name.trim().to_lowercase();
+    UNITS.iter()
+        .find(|unit| unit.name == name)
+        .ok_or(format!("unknown unit '{}'", name))
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum Amount {
+    Plain(i64),
+    Text(String),
+    Detailed { amount: f64, unit: String },
+}
+
+impl Amount {
+    pub fn quantity(&self) -> Result<(f64, &'static Unit), String> {
+        match self {
+            Amount::Plain(amount) => Ok((*amount as f64, unit("")?)),
+            Amount::Text(text) => {
+                let captures = amount_pattern().captures(text).ok_or(format!("invalid amount '{}'", text))?;
+                let amount = captures[1].parse::<f64>().map_err(|_| format!("invalid amount '{}'", text))?;
+                Ok((amount, unit(&captures[2])?))
+            }
+            Amount::Detailed { amount, unit: name } => Ok((*amount, unit(name)?)),
+        }
+    }
+
+    /// The same kind of amount with another value, given in the unit of this amount.
+    pub fn with_value(&self, value: f64) -> Amount {
+        let value = (value * 1000.0).round() / 1000.0;
+        match self {
+            Amount::Plain(_) => Amount::Plain(value.round() as i64),
+            Amount::Text(text) => {
+                let name = self.quantity().map(|(_, unit)| unit.name).unwrap_or("");
+                let separator = if text.trim_end().ends_with(&format!(" {}", name)) && !name.is_empty() { " " } else { "" };
+                Amount::Text(format!("{}{}{}", value, separator, name))
+            }
+            Amount::Detailed { unit, .. } => Amount::Detailed { amount: value, unit: unit.clone() },
+        }
+    }
+
+    pub fn is_plain(&self) -> bool {
+        matches!(self, Amount::Plain(_))
+    }
+}
+
+/// Converts `value` given in the base unit of `from` to the base unit of `to`, using the density
+/// in grams per millilitre between mass and volume.
+pub fn convert(value: f64, from: Dimension, to: Dimension, density: Option<f64>) -> Result<f64, String> {
+    let density = || density
+        .ok_or("missing density".to_string())
+        .and_then(|density| if density > 0.0 && density.is_finite() { Ok(density) } else { Err(format!("invalid density {}", density)) });
+    match (from, to) {
+        (from, to) if from == to => Ok(value),
+        (Dimension::Volume, Dimension::Mass) => Ok(value * density()?),
+        (Dimension::Mass, Dimension::Volume) => Ok(value / density()?),
+        (from, to) => Err(format!("can not convert {:?} to {:?}", from, to)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{convert, Amount, Dimension};
+
+    #[test]
+    fn test_quantity() {
+        let (amount, unit) = Amount::Text("1.5kg".to_string()).quantity().unwrap();
+        assert_eq!((amount, unit.name, unit.factor), (1.5, "kg", 1000.0));
+        let (amount, unit) = Amount::Text("3 fl oz".to_string()).quantity().unwrap();
+        assert_eq!((amount, unit.name), (3.0, "fl oz"));
+        let (amount, unit) = Amount::Detailed { amount: 2.0, unit: "Cup".to_string() }.quantity().unwrap();
+        assert_eq!((amount, unit.dimension), (2.0, Dimension::Volume));
+        assert!(Amount::Text("2 spoons".to_string()).quantity().is_err());
+    }
+
+    #[test]
+    fn test_with_value() {
+        assert_eq!(Amount::Text("1.5kg".to_string()).with_value(0.3), Amount::Text("0.3kg".to_string()));
+        assert_eq!(Amount::Text("2 cup".to_string()).with_value(0.5), Amount::Text("0.5 cup".to_string()));
+        assert_eq!(Amount::Plain(10).with_value(4.0), Amount::Plain(4));
+    }
+
+    #[test]
+    fn test_convert() {
+        assert_eq!(convert(100.0, Dimension::Volume, Dimension::Mass, Some(1.03)), Ok(103.0));
+        assert!(convert(100.0, Dimension::Volume, Dimension::Mass, None).is_err());
+        assert!(convert(1.0, Dimension::Count, Dimension::Mass, Some(1.0)).is_err());
+        for density in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(convert(100.0, Dimension::Volume, Dimension::Mass, Some(density)).is_err());
+            assert!(convert(100.0, Dimension::Mass, Dimension::Volume, Some(density)).is_err());
+        }
+    }
+}